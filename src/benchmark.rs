@@ -0,0 +1,119 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use rand::Rng;
+
+use crate::brain::{Brain, DenseBrain};
+use crate::consts::*;
+use crate::controller::Controller;
+use crate::game_manager::GameManager;
+
+
+/// Plays the current generation headless for the given number of episodes, first driven by its brains,
+/// then by each scripted controller in turn, and prints how well each of them did
+/// Every episode is played on a fresh map, under the same conditions as a regular generation
+pub fn run(mut game_manager: GameManager, episodes: usize) {
+
+    game_manager.set_quiet(true);
+
+    let brains: Vec<Brain> = game_manager.brains().cloned().collect();
+
+    println!("Benchmark over {} episodes of {} snakes", episodes, brains.len());
+
+    for controller in std::iter::once(Controller::Brain).chain(Controller::SCRIPTED) {
+
+        if !controller.fits(game_manager.map()) {
+            println!("{:>6}: skipped, it needs a map without walls inside its border", controller.name());
+            continue;
+        }
+
+        game_manager.set_controller(controller);
+
+        let mut fitness = Vec::with_capacity(episodes * brains.len());
+        for _ in 0..episodes {
+            fitness.extend(game_manager.evaluate(brains.clone()));
+        }
+
+        let mean = fitness.iter().sum::<f64>() / fitness.len().max(1) as f64;
+        let deviation = (fitness.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / fitness.len().max(1) as f64).sqrt();
+        let best = fitness.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        println!(
+            "{:>6}: mean fitness {:.2} ± {:.2}, best fitness {:.1}",
+            controller.name(),
+            mean,
+            deviation,
+            best
+        );
+    }
+}
+
+
+/// A layer stored the way layers used to be, with one vector of weights per row, and the biases
+type NestedLayer = (Vec<Vec<f64>>, Vec<f64>);
+
+
+/// Builds a nested layer from its weights and biases, in the order of `DenseBrain::parameters`
+fn nested_layer(parameters: &[f64], input_size: usize) -> NestedLayer {
+    let output_size = parameters.len() / (input_size + 1);
+    let (weights, biases) = parameters.split_at(output_size * input_size);
+    (weights.chunks_exact(input_size).map(|row| row.to_vec()).collect(), biases.to_vec())
+}
+
+
+/// The forward pass of a layer as it used to be done, allocating the outputs at every call
+fn nested_forward((weights, biases): &NestedLayer, input: &[f64]) -> Vec<f64> {
+
+    let mut output: Vec<f64> = Vec::with_capacity(weights.len());
+
+    for (weights_row, bias) in weights.iter().zip(biases.iter()) {
+        let mut sum = 0.0;
+        for (weight, value) in weights_row.iter().zip(input.iter()) {
+            sum += weight * value;
+        }
+        sum += bias;
+        output.push(sum);
+    }
+
+    output
+}
+
+
+/// Runs the given forward passes and returns how many ran per second
+fn passes_per_second(passes: usize, run: impl FnOnce()) -> f64 {
+    let start = Instant::now();
+    run();
+    passes as f64 / start.elapsed().as_secs_f64()
+}
+
+
+/// Measures how many forward passes of a dense brain run per second on random inputs:
+/// with the weights nested by row and fresh outputs at every pass, as they used to be,
+/// and with the flat weights and the brain's scratch buffers
+pub fn throughput(passes: usize) {
+
+    let mut rng = rand::thread_rng();
+    let inputs: Vec<f64> = (0..passes * SIGHT_INPUT_SIZE).map(|_| rng.gen_range(-1.0..1.0)).collect();
+
+    let mut brain = DenseBrain::new();
+    let parameters = brain.parameters();
+    let (input_layer, output_layer) = parameters.split_at(SIGHT_INPUT_SIZE * (SIGHT_INPUT_SIZE + 1));
+    let nested = [nested_layer(input_layer, SIGHT_INPUT_SIZE), nested_layer(output_layer, SIGHT_INPUT_SIZE)];
+
+    let nested_speed = passes_per_second(passes, || {
+        for input in inputs.chunks_exact(SIGHT_INPUT_SIZE) {
+            let hidden = nested_forward(&nested[0], input);
+            black_box(nested_forward(&nested[1], &hidden));
+        }
+    });
+
+    let flat_speed = passes_per_second(passes, || {
+        for input in inputs.chunks_exact(SIGHT_INPUT_SIZE) {
+            black_box(brain.evaluate(input));
+        }
+    });
+
+    println!("Forward passes of a dense brain with {} inputs, over {} random inputs", SIGHT_INPUT_SIZE, passes);
+    println!("  nested rows, allocating: {:>10.0} passes/s", nested_speed);
+    println!("  flat rows, scratch buffers: {:>7.0} passes/s (x{:.2})", flat_speed, flat_speed / nested_speed);
+}
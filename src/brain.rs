@@ -1,144 +1,628 @@
 use crate::consts::*;
-use crate::map::{SubmapMatrix, Block};
-use crate::snake::Direction;
+use crate::map::{SubmapMatrix, Block, Item};
+use crate::snake::{Direction, SnakeId};
+use crate::neat::Genome;
+use crate::mutation::Mutation;
+use crate::policy::Policy;
 
 use rand::Rng;
 use serde_derive::{Serialize, Deserialize};
 
 
+/// A fully connected layer
+/// The weights are stored flat, one row of `input_size` weights per output, so that a forward pass reads them in order
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "LayerFile", into = "LayerFile")]
 struct Layer {
 
+    input_size: usize,
+    weights: Vec<f64>,
+    biases: Vec<f64>,
+    /// The layer's own mutation step, in the self-adaptive mode
+    step_size: Option<f64>,
+
+}
+
+
+/// How a layer is saved, with the weights nested by row, as in the generation files saved before they were stored flat
+#[derive(Clone, Serialize, Deserialize)]
+struct LayerFile {
+
     weights: Vec<Vec<f64>>,
     biases: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    step_size: Option<f64>,
 
 }
 
 
+/// Files with rows of different lengths, or a bias count that doesn't match the rows, are rejected
+impl TryFrom<LayerFile> for Layer {
+
+    type Error = String;
+
+    fn try_from(file: LayerFile) -> Result<Self, Self::Error> {
+
+        let input_size = file.weights.first().map_or(0, |row| row.len());
+
+        if let Some(row) = file.weights.iter().position(|row| row.len() != input_size) {
+            return Err(format!("row {} of the weights has {} entries, expected {}", row, file.weights[row].len(), input_size));
+        }
+
+        if file.biases.len() != file.weights.len() {
+            return Err(format!("the layer has {} biases for {} rows of weights", file.biases.len(), file.weights.len()));
+        }
+
+        Ok(Layer {
+            input_size,
+            weights: file.weights.into_iter().flatten().collect(),
+            biases: file.biases,
+            step_size: file.step_size,
+        })
+    }
+
+}
+
+
+impl From<Layer> for LayerFile {
+    fn from(layer: Layer) -> Self {
+        LayerFile {
+            weights: layer.rows().map(|row| row.to_vec()).collect(),
+            biases: layer.biases,
+            step_size: layer.step_size,
+        }
+    }
+}
+
+
 impl Layer {
 
     pub fn new(input_size: usize, layer_size: usize) -> Self {
 
         let mut rand = rand::thread_rng();
-        
-        let mut biases: Vec<f64> = Vec::with_capacity(layer_size);
-        let mut weights: Vec<Vec<f64>> = Vec::with_capacity(layer_size);
 
-        for _ in 0..layer_size {
-            biases.push(rand.gen_range(-1.0..1.0));
+        Self {
+            input_size,
+            weights: (0..input_size * layer_size).map(|_| rand.gen_range(-1.0..1.0)).collect(),
+            biases: (0..layer_size).map(|_| rand.gen_range(-1.0..1.0)).collect(),
+            step_size: None,
+        }
+    }
+
+
+    pub fn output_size(&self) -> usize {
+        self.biases.len()
+    }
+
+
+    /// The weights of each output
+    fn rows(&self) -> impl Iterator<Item = &[f64]> {
+        self.weights.chunks_exact(self.input_size.max(1))
+    }
 
-            let mut weights_row: Vec<f64> = Vec::with_capacity(input_size);
-            for _ in 0..input_size {
-                weights_row.push(rand.gen_range(-1.0..1.0));
-            }
 
-            weights.push(weights_row);
+    pub fn forward(&self, input: &[f64]) -> Vec<f64> {
+        let mut output = vec![0.0; self.output_size()];
+        self.forward_into(input, &mut output);
+        output
+    }
+
+
+    /// Computes the outputs of the layer into the given buffer, without allocating
+    pub fn forward_into(&self, input: &[f64], output: &mut [f64]) {
+        for ((weights_row, bias), output) in self.rows().zip(self.biases.iter()).zip(output.iter_mut()) {
+            *output = dot(weights_row, input) + bias;
         }
+    }
 
-        Self {
-            weights,
-            biases,
+
+    /// Returns the sum of the absolute differences between the parameters of two layers, and the number of parameters
+    pub fn difference(&self, other: &Layer) -> (f64, usize) {
+
+        let weights = self.weights.iter().zip(other.weights.iter());
+        let biases = self.biases.iter().zip(other.biases.iter());
+
+        weights.chain(biases).fold((0.0, 0), |(sum, count), (a, b)| (sum + (a - b).abs(), count + 1))
+    }
+
+
+    /// The weights followed by the biases, in row-major order
+    pub fn parameters(&self) -> impl Iterator<Item = &f64> {
+        self.weights.iter().chain(self.biases.iter())
+    }
+
+
+    /// Sets the weights and biases from the start of the given parameters, in the order of `parameters`
+    /// Returns the number of parameters used
+    pub fn set_parameters(&mut self, parameters: &[f64]) -> usize {
+
+        let targets = self.weights.iter_mut().chain(self.biases.iter_mut());
+        let mut count = 0;
+
+        for (target, parameter) in targets.zip(parameters.iter()) {
+            *target = *parameter;
+            count += 1;
         }
+
+        count
     }
 
 
-    pub fn forward(&self, input: &Vec<f64>) -> Vec<f64> {
-        
-        let mut output: Vec<f64> = Vec::with_capacity(self.weights.len());
+    /// Backpropagates the gradient of the loss with respect to the outputs of the layer
+    /// Updates the weights and biases by gradient descent, and returns the gradient with respect to the inputs
+    pub fn backward(&mut self, input: &[f64], output_gradient: &[f64], learning_rate: f64) -> Vec<f64> {
+
+        let mut input_gradient = vec![0.0; input.len()];
+        let rows = self.weights.chunks_exact_mut(self.input_size.max(1));
 
-        for i in 0..self.weights.len() {
-            let mut sum = 0.0;
-            for j in 0..self.weights[i].len() {
-                sum += self.weights[i][j] * input[j];
+        for ((weights_row, bias), gradient) in rows.zip(self.biases.iter_mut()).zip(output_gradient.iter()) {
+            for ((weight, value), input_gradient) in weights_row.iter_mut().zip(input.iter()).zip(input_gradient.iter_mut()) {
+                *input_gradient += *weight * gradient;
+                *weight -= learning_rate * gradient * value;
             }
-            sum += self.biases[i];
-            output.push(sum);
+            *bias -= learning_rate * gradient;
         }
 
-        output
+        input_gradient
     }
 
 
-    pub fn mutate(&mut self) {
-        let mut rng = rand::thread_rng();
+    pub fn mutate(&mut self, mutation: &Mutation) {
+        let parameters = self.weights.iter_mut().chain(self.biases.iter_mut());
+        mutation.apply(parameters, &mut self.step_size);
+    }
 
-        for i in 0..self.weights.len() {
+}
 
-            if rng.gen_range(0.0..1.0) < MUTATION_CHANCE {
-                self.biases[i] += rng.gen_range(-1.0..1.0) * MAX_MUTATION;
-            }
 
-            for j in 0..self.weights[i].len() {
-                if rng.gen_range(0.0..1.0) < MUTATION_CHANCE {
-                    self.weights[i][j] += rng.gen_range(-1.0..1.0) * MAX_MUTATION;
-                }
-            }
-        }
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+
+    let length = a.len().min(b.len());
+    let (a, b) = (&a[..length], &b[..length]);
 
+    let mut sums = [0.0; DOT_LANES];
+    for (a, b) in a.chunks_exact(DOT_LANES).zip(b.chunks_exact(DOT_LANES)) {
+        for lane in 0..DOT_LANES {
+            sums[lane] += a[lane] * b[lane];
+        }
     }
 
+    let remainder = length - length % DOT_LANES;
+    let tail: f64 = a[remainder..].iter().zip(b[remainder..].iter()).map(|(a, b)| a * b).sum();
+
+    sums.iter().sum::<f64>() + tail
 }
 
 
+/// A fixed topology network, with a single fully connected hidden layer
 #[derive(Clone, Serialize, Deserialize)]
-pub struct Brain {
+pub struct DenseBrain {
 
     input_layer: Layer,
     output_layer: Layer,
+    /// The outputs of the hidden layer at the last step
+    /// Kept with the brain, along with the other scratch buffers, so that thinking doesn't allocate
+    #[serde(skip)]
+    hidden: Vec<f64>,
+    /// The outputs of the brain at the last step, returned by `evaluate`
+    #[serde(skip)]
+    output: Vec<f64>,
 
 }
 
 
-impl Brain {
+impl DenseBrain {
+
+    pub fn new() -> Self {
+        DenseBrain {
+            input_layer: Layer::new(SIGHT_INPUT_SIZE, SIGHT_INPUT_SIZE),
+            output_layer: Layer::new(SIGHT_INPUT_SIZE, 4),
+            hidden: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
 
-    pub fn mutate(&mut self) {
+    pub fn mutate(&mut self, mutation: &Mutation) {
         
-        self.input_layer.mutate();
-        self.output_layer.mutate();
+        self.input_layer.mutate(mutation);
+        self.output_layer.mutate(mutation);
         
     }
 
-    pub fn new() -> Self {
-        Brain {
-            input_layer: Layer::new(SIGHT_INPUT_SIZE, SIGHT_INPUT_SIZE),
-            output_layer: Layer::new(SIGHT_INPUT_SIZE, 4),
+
+    /// The direction with the highest output
+    pub fn choose(&self, input: &[f64]) -> Direction {
+        argmax_direction(&self.forward(input))
+    }
+
+
+    /// Takes a gradient descent step on the softmax cross-entropy loss of choosing the target direction
+    /// Returns the loss before the step
+    pub fn train(&mut self, input: &[f64], target: Direction, learning_rate: f64) -> f64 {
+
+        let hidden = self.input_layer.forward(input);
+        let output = self.output_layer.forward(&hidden);
+
+        let mut gradient = softmax(&output);
+        let loss = -gradient[target.index()].max(f64::MIN_POSITIVE).ln();
+        gradient[target.index()] -= 1.0;
+
+        self.backward(input, &hidden, &gradient, learning_rate);
+
+        loss
+    }
+
+
+    /// Takes a gradient descent step moving one output toward the target value, on the Huber loss
+    /// The error is clipped to [-1, 1], so that rare large errors don't destabilize the training
+    /// Returns the loss before the step
+    pub fn regress(&mut self, input: &[f64], output: usize, target: f64, learning_rate: f64) -> f64 {
+
+        let hidden = self.input_layer.forward(input);
+        let error = self.output_layer.forward(&hidden)[output] - target;
+
+        let loss = if error.abs() <= 1.0 { 0.5 * error * error } else { error.abs() - 0.5 };
+        let mut gradient = vec![0.0; 4];
+        gradient[output] = error.clamp(-1.0, 1.0);
+
+        self.backward(input, &hidden, &gradient, learning_rate);
+
+        loss
+    }
+
+
+    /// Backpropagates the gradient of the loss with respect to the outputs through both layers
+    fn backward(&mut self, input: &[f64], hidden: &[f64], output_gradient: &[f64], learning_rate: f64) {
+        let hidden_gradient = self.output_layer.backward(hidden, output_gradient, learning_rate);
+        self.input_layer.backward(input, &hidden_gradient, learning_rate);
+    }
+
+
+    /// All the weights and biases of the brain as a single vector
+    pub fn parameters(&self) -> Vec<f64> {
+        self.input_layer.parameters().chain(self.output_layer.parameters()).copied().collect()
+    }
+
+
+    /// Returns a brain with the same structure and the given parameters, in the order of `parameters`
+    pub fn with_parameters(&self, parameters: &[f64]) -> DenseBrain {
+
+        let mut brain = self.clone();
+
+        let used = brain.input_layer.set_parameters(parameters);
+        brain.output_layer.set_parameters(&parameters[used..]);
+
+        brain
+    }
+
+
+    /// The mean absolute difference between the weights and biases of two brains
+    pub fn distance(&self, other: &DenseBrain) -> f64 {
+
+        let (input_sum, input_count) = self.input_layer.difference(&other.input_layer);
+        let (output_sum, output_count) = self.output_layer.difference(&other.output_layer);
+
+        (input_sum + output_sum) / (input_count + output_count).max(1) as f64
+    }
+
+
+    pub fn forward(&self, input: &[f64]) -> Vec<f64> {
+        let out = self.input_layer.forward(input);
+        self.output_layer.forward(&out)
+    }
+
+
+    /// Same as `forward`, but computes the outputs in the brain's scratch buffers instead of allocating
+    pub fn evaluate(&mut self, input: &[f64]) -> &[f64] {
+
+        self.hidden.resize(self.input_layer.output_size(), 0.0);
+        self.output.resize(self.output_layer.output_size(), 0.0);
+
+        self.input_layer.forward_into(input, &mut self.hidden);
+        self.output_layer.forward_into(&self.hidden, &mut self.output);
+
+        &self.output
+    }
+
+}
+
+
+/// An Elman network, whose hidden layer also sees its own output of the previous step
+/// The hidden state carries a memory across the steps of a snake's life, and starts from zeros when it spawns
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecurrentBrain {
+
+    /// The size of the hidden state
+    state_size: usize,
+    /// Takes the sight followed by the previous state
+    recurrent_layer: Layer,
+    readout_layer: Layer,
+    /// Not saved, since each snake starts with an empty memory
+    #[serde(skip)]
+    state: Vec<f64>,
+    /// The sight followed by the previous state, as given to the recurrent layer
+    #[serde(skip)]
+    recurrent_input: Vec<f64>,
+    /// The outputs of the readout layer at the last step, returned by `step`
+    #[serde(skip)]
+    output: Vec<f64>,
+
+}
+
+
+impl RecurrentBrain {
+
+    pub fn new(state_size: usize) -> Self {
+        RecurrentBrain {
+            state_size,
+            recurrent_layer: Layer::new(SIGHT_INPUT_SIZE + state_size, state_size),
+            readout_layer: Layer::new(state_size, 4),
+            state: vec![0.0; state_size],
+            recurrent_input: Vec::new(),
+            output: Vec::new(),
         }
     }
 
 
-    pub fn think(&mut self, input: &SubmapMatrix) -> Direction {
+    pub fn mutate(&mut self, mutation: &Mutation) {
+        self.recurrent_layer.mutate(mutation);
+        self.readout_layer.mutate(mutation);
+    }
 
-        let input: Vec<f64> = input.to_vec().iter().flatten().map(|block| 
-            match block {
-                Block::Void => 0.0,
-                Block::Wall => -1.0,
-                Block::SnakeTail => -1.0,
-                Block::SnakeHead => -1.0,
-                Block::Apple => 1.0,
-            }).collect();
 
-        let out = self.input_layer.forward(&input);
-        
-        let out = self.output_layer.forward(&out);
-
-        let mut max = 0.0;
-        let mut max_index = 0;
-        for i in 0..out.len() {
-            if out[i] > max {
-                max = out[i];
-                max_index = i;
-            }
+    /// Updates the hidden state with the new input, and returns the outputs
+    pub fn step(&mut self, input: &[f64]) -> &[f64] {
+
+        // Brains loaded from a file start without a state
+        self.state.resize(self.state_size, 0.0);
+        self.output.resize(self.readout_layer.output_size(), 0.0);
+
+        self.recurrent_input.clear();
+        self.recurrent_input.extend_from_slice(input);
+        self.recurrent_input.extend_from_slice(&self.state);
+
+        self.recurrent_layer.forward_into(&self.recurrent_input, &mut self.state);
+        for value in self.state.iter_mut() {
+            *value = value.tanh();
         }
 
-        match max_index {
-            0 => Direction::Up,
-            1 => Direction::Down,
-            2 => Direction::Left,
-            3 => Direction::Right,
-            _ => panic!("Invalid direction index"),
+        self.readout_layer.forward_into(&self.state, &mut self.output);
+
+        &self.output
+    }
+
+
+    /// Forgets everything, as when the snake spawns
+    pub fn reset_state(&mut self) {
+        self.state.clear();
+        self.state.resize(self.state_size, 0.0);
+    }
+
+
+    /// The mean absolute difference between the weights and biases of two brains
+    /// Brains with different state sizes are infinitely far apart
+    pub fn distance(&self, other: &RecurrentBrain) -> f64 {
+
+        if self.state_size != other.state_size {
+            return f64::INFINITY;
         }
 
+        let (recurrent_sum, recurrent_count) = self.recurrent_layer.difference(&other.recurrent_layer);
+        let (readout_sum, readout_count) = self.readout_layer.difference(&other.readout_layer);
+
+        (recurrent_sum + readout_sum) / (recurrent_count + readout_count).max(1) as f64
+    }
+
+}
+
+
+/// The kind of brain new snakes are spawned with
+#[derive(Clone, Copy, PartialEq)]
+pub enum BrainKind {
+
+    /// Fixed topology network
+    Dense,
+    /// Topology evolving network
+    Neat,
+    /// Network with a memory, holding a hidden state of the given size
+    Recurrent(usize),
+
+}
+
+
+impl BrainKind {
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "dense" => BrainKind::Dense,
+            "neat" => BrainKind::Neat,
+            "recurrent" => BrainKind::Recurrent(DEFAULT_RECURRENT_STATE_SIZE),
+            _ => panic!("Unknown brain: {:?}. Expected \"dense\", \"neat\" or \"recurrent\"", name),
+        }
     }
 
 }
 
+
+/// The network controlling a snake
+/// Serialized without a tag, so that generation files saved before NEAT brains existed still load
+/// The kinds of brain are told apart by the names of their fields
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Brain {
+
+    Dense(DenseBrain),
+    Neat(Genome),
+    Recurrent(RecurrentBrain),
+
+}
+
+
+impl Brain {
+
+    pub fn new(kind: BrainKind) -> Self {
+        match kind {
+            BrainKind::Dense => Brain::Dense(DenseBrain::new()),
+            BrainKind::Neat => Brain::Neat(Genome::new()),
+            BrainKind::Recurrent(state_size) => Brain::Recurrent(RecurrentBrain::new(state_size)),
+        }
+    }
+
+
+    pub fn mutate(&mut self, mutation: &Mutation) {
+        match self {
+            Brain::Dense(brain) => brain.mutate(mutation),
+            Brain::Neat(genome) => genome.mutate(mutation),
+            Brain::Recurrent(brain) => brain.mutate(mutation),
+        }
+    }
+
+
+    /// How different two brains are, used to group them into species
+    /// Brains of different kinds are infinitely far apart
+    pub fn distance(&self, other: &Brain) -> f64 {
+        match (self, other) {
+            (Brain::Dense(brain), Brain::Dense(other)) => brain.distance(other),
+            (Brain::Neat(genome), Brain::Neat(other)) => genome.compatibility_distance(other),
+            (Brain::Recurrent(brain), Brain::Recurrent(other)) => brain.distance(other),
+            _ => f64::INFINITY,
+        }
+    }
+
+
+    /// Brains closer than this distance belong to the same species
+    pub fn compatibility_threshold(&self) -> f64 {
+        match self {
+            Brain::Dense(_) | Brain::Recurrent(_) => DENSE_COMPATIBILITY_THRESHOLD,
+            Brain::Neat(_) => NEAT_COMPATIBILITY_THRESHOLD,
+        }
+    }
+
+
+    /// Registers the structure of a brain loaded from a file, so that new innovations don't collide with it
+    pub fn register_innovations(&self) {
+        if let Brain::Neat(genome) = self {
+            genome.register_innovations();
+        }
+    }
+
+
+    /// Clears the memory of recurrent brains, as when the snake spawns
+    pub fn reset_state(&mut self) {
+        if let Brain::Recurrent(brain) = self {
+            brain.reset_state();
+        }
+    }
+
+
+    /// Chooses a direction from the encoded sight of a snake moving in the current direction
+    pub fn think(&mut self, input: &[f64], policy: &Policy, current: Direction) -> Direction {
+        match self {
+            Brain::Dense(brain) => policy.choose(brain.evaluate(input), current),
+            Brain::Neat(genome) => policy.choose(&genome.activate(input), current),
+            Brain::Recurrent(brain) => policy.choose(brain.step(input), current),
+        }
+    }
+
+}
+
+
+/// Converts the sight of the snake with the given id into the input of a brain
+/// The snake's own body, its teammates' bodies and its foes' bodies are all seen differently
+pub fn encode_sight(input: &SubmapMatrix, own_id: SnakeId, teams: &[usize]) -> Vec<f64> {
+    let mut encoded = Vec::with_capacity(SIGHT_INPUT_SIZE);
+    encode_sight_into(input, own_id, teams, &mut encoded);
+    encoded
+}
+
+
+/// Same as `encode_sight`, but writes into the given buffer, so that it can be reused across the steps
+pub fn encode_sight_into(input: &SubmapMatrix, own_id: SnakeId, teams: &[usize], encoded: &mut Vec<f64>) {
+
+    let is_friend = |id: SnakeId| teams[id] == teams[own_id];
+
+    encoded.clear();
+    encoded.extend(input.iter().flatten().map(|block| 
+        match block {
+            Block::Void => 0.0,
+            Block::Wall => -1.0,
+            Block::SnakeTail(id) |
+            Block::SnakeHead(id) if *id == own_id => -1.0,
+            Block::SnakeTail(id) if is_friend(*id) => -0.9,
+            Block::SnakeHead(id) if is_friend(*id) => -0.7,
+            Block::SnakeTail(_) => -0.8,
+            Block::SnakeHead(_) => -0.6,
+            Block::Item(Item::Apple) => 1.0,
+            Block::Item(Item::GoldenApple) => 1.5,
+            Block::Item(Item::ShrinkPill) => 0.5,
+            Block::Item(Item::Poison) => -0.5,
+        }));
+}
+
+
+/// Converts the outputs of a brain into probabilities
+pub fn softmax(values: &[f64]) -> Vec<f64> {
+
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let exponentials: Vec<f64> = values.iter().map(|value| (value - max).exp()).collect();
+    let sum: f64 = exponentials.iter().sum();
+
+    exponentials.iter().map(|exponential| exponential / sum).collect()
+}
+
+
+/// The direction with the highest output
+/// The first one wins ties, including when all the outputs are ruled out with negative infinity
+pub fn argmax_direction(out: &[f64]) -> Direction {
+
+    let mut max = f64::NEG_INFINITY;
+    let mut max_index = 0;
+    for (i, value) in out.iter().enumerate() {
+        if *value > max {
+            max = *value;
+            max_index = i;
+        }
+    }
+
+    match max_index {
+        0 => Direction::Up,
+        1 => Direction::Down,
+        2 => Direction::Left,
+        3 => Direction::Right,
+        _ => panic!("Invalid direction index"),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+
+    #[test]
+    fn layer_files_round_trip() {
+
+        let layer = Layer::new(3, 2);
+        let json = serde_json::to_string(&layer).unwrap();
+        let loaded: Layer = serde_json::from_str(&json).unwrap();
+
+        // JSON may round the last digit of the values
+        assert_eq!(loaded.input_size, 3);
+        assert_eq!(loaded.weights.len(), layer.weights.len());
+        assert!(loaded.parameters().zip(layer.parameters()).all(|(a, b)| (a - b).abs() < 1e-12));
+    }
+
+
+    #[test]
+    fn layer_files_with_ragged_rows_are_rejected() {
+
+        let ragged = r#"{"weights": [[1.0, 2.0], [3.0]], "biases": [0.0, 0.0]}"#;
+        assert!(serde_json::from_str::<Layer>(ragged).is_err());
+
+        let missing_bias = r#"{"weights": [[1.0, 2.0], [3.0, 4.0]], "biases": [0.0]}"#;
+        assert!(serde_json::from_str::<Layer>(missing_bias).is_err());
+    }
+
+}
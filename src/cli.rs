@@ -0,0 +1,434 @@
+use crate::brain::BrainKind;
+use crate::consts::*;
+use crate::generator::{Generator, GeneratorKind};
+use crate::map::{CorpseRules, Item, ItemWeights};
+use crate::novelty::NoveltySearch;
+use crate::islands::{Migration, Topology};
+use crate::mutation::{MutationConfig, Operator, Schedule};
+use crate::controller::Controller;
+use crate::policy::Policy;
+
+
+/// The frontend used to display the game
+pub enum Renderer {
+
+    /// OpenGL window
+    Window,
+    /// ANSI colored output in the terminal
+    Terminal,
+
+}
+
+
+impl Renderer {
+
+    fn from_name(name: &str) -> Self {
+        match name {
+            "window" => Renderer::Window,
+            "terminal" => Renderer::Terminal,
+            _ => panic!("Unknown renderer: {:?}. Expected \"window\" or \"terminal\"", name),
+        }
+    }
+
+}
+
+
+/// Headless image export, in place of a renderer
+pub enum Export {
+
+    /// Snapshot of the map after the simulated steps
+    Png(String),
+    /// Animation of all the simulated steps
+    Gif(String),
+
+}
+
+
+/// The options the program was launched with
+pub struct Options {
+
+    pub renderer: Renderer,
+    /// The generation file to load the brains from
+    pub generation_file: Option<String>,
+    pub export: Option<Export>,
+    /// The name of a built-in layout or the path of a layout file
+    pub layout: String,
+    /// Generates the layouts procedurally, in place of the fixed layout
+    pub generator: Option<Generator>,
+    /// Makes the generated layouts harder as the snakes improve
+    pub curriculum: bool,
+    /// Toroidal maps, where moving off an edge re-enters on the opposite one
+    pub wrap: bool,
+    /// Relative spawn chances of the items
+    pub item_weights: ItemWeights,
+    /// If set, dead snakes leave apples behind
+    pub corpse_rules: Option<CorpseRules>,
+    /// Fitness awarded for each snake killed
+    pub kill_reward: f64,
+    /// The number of teams the snakes are split into, or 0 to play alone
+    pub teams: usize,
+    /// The kind of brain new random snakes are spawned with
+    pub brain_kind: BrainKind,
+    /// If set, the snakes are selected by the novelty of their behavior as well as their fitness
+    pub novelty_search: Option<NoveltySearch>,
+    pub mutation: MutationConfig,
+    pub policy: Policy,
+    /// Makes the snakes take their best choice, even if they sample their directions when training
+    pub watch: bool,
+    /// The number of steps simulated when exporting
+    pub steps: usize,
+    /// The number of populations evolved headless in parallel, or 0 to run a single one
+    pub islands: usize,
+    pub migration: Migration,
+    /// Optimizes a dense brain with an evolution strategy, headless
+    pub evolution_strategy: bool,
+    /// Trains a dense brain with deep Q-learning, headless
+    pub reinforcement: bool,
+    /// The number of generations the islands, the evolution strategy or the reinforcement learning run for
+    pub generations: usize,
+    /// Records the pathfinding bot playing headless to the given dataset file
+    pub record_demonstrations: Option<String>,
+    /// Trains a brain to imitate the demonstrations of the given dataset file
+    pub imitation_dataset: Option<String>,
+    /// The number of passes over the demonstrations when training
+    pub epochs: usize,
+    /// Scripted snakes playing alongside every generation
+    pub bots: Vec<Controller>,
+    /// If set, compares the brains with the scripted controllers over the given number of episodes, headless
+    pub benchmark_episodes: Option<usize>,
+    /// If set, measures the speed of the forward pass of a brain over the given number of inputs, then exits
+    pub throughput_passes: Option<usize>,
+
+}
+
+
+impl Options {
+
+    /// Parses the command line arguments, excluding the program name
+    /// Panics on invalid arguments
+    pub fn parse(args: &[String]) -> Self {
+
+        let mut options = Options {
+            renderer: Renderer::Window,
+            generation_file: None,
+            export: None,
+            steps: DEFAULT_EXPORT_STEPS,
+            layout: DEFAULT_LAYOUT.to_string(),
+            generator: None,
+            curriculum: false,
+            wrap: false,
+            item_weights: DEFAULT_ITEM_WEIGHTS,
+            corpse_rules: None,
+            kill_reward: 0.0,
+            teams: 0,
+            brain_kind: BrainKind::Dense,
+            novelty_search: None,
+            mutation: MutationConfig::new(),
+            policy: Policy::new(),
+            watch: false,
+            islands: 0,
+            migration: Migration {
+                topology: Topology::Ring,
+                interval: DEFAULT_MIGRATION_INTERVAL,
+                size: DEFAULT_MIGRATION_SIZE,
+            },
+            evolution_strategy: false,
+            reinforcement: false,
+            generations: DEFAULT_ISLAND_GENERATIONS,
+            record_demonstrations: None,
+            imitation_dataset: None,
+            epochs: DEFAULT_IMITATION_EPOCHS,
+            bots: Vec::new(),
+            benchmark_episodes: None,
+            throughput_passes: None,
+        };
+
+        let mut generator_kind = None;
+        let mut density = DEFAULT_GENERATOR_DENSITY;
+        let mut state_size = None;
+        let mut seed = rand::random::<u64>();
+        let mut corpse_ratio = None;
+        let mut corpse_decay = DEFAULT_CORPSE_DECAY;
+
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+
+                "--renderer" => {
+                    let name = args.next().expect("Missing value for --renderer");
+                    options.renderer = Renderer::from_name(name);
+                },
+
+                "--tui" => options.renderer = Renderer::Terminal,
+
+                "--export-png" => {
+                    let path = args.next().expect("Missing value for --export-png");
+                    options.export = Some(Export::Png(path.clone()));
+                },
+
+                "--record-gif" => {
+                    let path = args.next().expect("Missing value for --record-gif");
+                    options.export = Some(Export::Gif(path.clone()));
+                },
+
+                "--map" => {
+                    let layout = args.next().expect("Missing value for --map");
+                    options.layout = layout.clone();
+                },
+
+                "--generate" => {
+                    let name = args.next().expect("Missing value for --generate");
+                    generator_kind = Some(GeneratorKind::from_name(name));
+                },
+
+                "--density" => {
+                    let value = args.next().expect("Missing value for --density");
+                    density = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid density: {:?}", value)
+                    );
+                },
+
+                "--seed" => {
+                    let value = args.next().expect("Missing value for --seed");
+                    seed = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid seed: {:?}", value)
+                    );
+                },
+
+                "--curriculum" => options.curriculum = true,
+
+                "--wrap" => options.wrap = true,
+
+                "--item-weights" => {
+                    let value = args.next().expect("Missing value for --item-weights");
+                    options.item_weights = parse_item_weights(value);
+                },
+
+                "--corpses" => {
+                    let value = args.next().expect("Missing value for --corpses");
+                    let ratio: f64 = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid corpse conversion ratio: {:?}", value)
+                    );
+                    corpse_ratio = Some(ratio.clamp(0.0, 1.0));
+                },
+
+                "--corpse-decay" => {
+                    let value = args.next().expect("Missing value for --corpse-decay");
+                    corpse_decay = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid corpse decay time: {:?}", value)
+                    );
+                },
+
+                "--arena" => options.kill_reward = DEFAULT_KILL_REWARD,
+
+                "--kill-reward" => {
+                    let value = args.next().expect("Missing value for --kill-reward");
+                    options.kill_reward = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid kill reward: {:?}", value)
+                    );
+                },
+
+                "--teams" => {
+                    let value = args.next().expect("Missing value for --teams");
+                    options.teams = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid number of teams: {:?}", value)
+                    );
+                },
+
+                "--brain" => {
+                    let name = args.next().expect("Missing value for --brain");
+                    options.brain_kind = BrainKind::from_name(name);
+                },
+
+                "--state-size" => {
+                    let value = args.next().expect("Missing value for --state-size");
+                    state_size = Some(value.parse().unwrap_or_else(
+                        |_| panic!("Invalid state size: {:?}", value)
+                    ));
+                },
+
+                "--novelty" => {
+                    let value = args.next().expect("Missing value for --novelty");
+                    let weight = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid novelty weight: {:?}", value)
+                    );
+                    options.novelty_search = Some(NoveltySearch::new(weight));
+                },
+
+                "--mutation" => {
+                    let name = args.next().expect("Missing value for --mutation");
+                    options.mutation.operator = Operator::from_name(name);
+                },
+
+                "--mutation-schedule" => {
+                    let name = args.next().expect("Missing value for --mutation-schedule");
+                    options.mutation.schedule = Schedule::from_name(name);
+                },
+
+                "--self-adaptive" => options.mutation.self_adaptive = true,
+
+                "--sample" => {
+                    let value = args.next().expect("Missing value for --sample");
+                    let temperature: f64 = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid temperature: {:?}", value)
+                    );
+                    if temperature <= 0.0 {
+                        panic!("The temperature must be positive, got {}", temperature);
+                    }
+                    options.policy.temperature = Some(temperature);
+                },
+
+                "--mask-reverse" => options.policy.mask_reverse = true,
+
+                "--watch" => options.watch = true,
+
+                "--islands" => {
+                    let value = args.next().expect("Missing value for --islands");
+                    options.islands = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid number of islands: {:?}", value)
+                    );
+                },
+
+                "--topology" => {
+                    let name = args.next().expect("Missing value for --topology");
+                    options.migration.topology = Topology::from_name(name);
+                },
+
+                "--migration-interval" => {
+                    let value = args.next().expect("Missing value for --migration-interval");
+                    options.migration.interval = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid migration interval: {:?}", value)
+                    );
+                },
+
+                "--migration-size" => {
+                    let value = args.next().expect("Missing value for --migration-size");
+                    options.migration.size = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid migration size: {:?}", value)
+                    );
+                },
+
+                "--es" => options.evolution_strategy = true,
+
+                "--dqn" => options.reinforcement = true,
+
+                "--generations" => {
+                    let value = args.next().expect("Missing value for --generations");
+                    options.generations = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid number of generations: {:?}", value)
+                    );
+                },
+
+                "--record-demos" => {
+                    let path = args.next().expect("Missing value for --record-demos");
+                    options.record_demonstrations = Some(path.clone());
+                },
+
+                "--imitate" => {
+                    let path = args.next().expect("Missing value for --imitate");
+                    options.imitation_dataset = Some(path.clone());
+                },
+
+                "--bots" => {
+                    let value = args.next().expect("Missing value for --bots");
+                    options.bots = value.split(',').map(Controller::from_name).collect();
+                },
+
+                "--benchmark" => {
+                    let value = args.next().expect("Missing value for --benchmark");
+                    options.benchmark_episodes = Some(value.parse().unwrap_or_else(
+                        |_| panic!("Invalid number of episodes: {:?}", value)
+                    ));
+                },
+
+                "--throughput" => {
+                    let value = args.next().expect("Missing value for --throughput");
+                    options.throughput_passes = Some(value.parse().unwrap_or_else(
+                        |_| panic!("Invalid number of passes: {:?}", value)
+                    ));
+                },
+
+                "--epochs" => {
+                    let value = args.next().expect("Missing value for --epochs");
+                    options.epochs = value.parse().unwrap_or_else(
+                        |_| panic!("Invalid number of epochs: {:?}", value)
+                    );
+                },
+
+                "--steps" => {
+                    let steps = args.next().expect("Missing value for --steps");
+                    options.steps = steps.parse().unwrap_or_else(
+                        |_| panic!("Invalid number of steps: {:?}", steps)
+                    );
+                },
+
+                _ if arg.starts_with("--") => panic!("Unknown option: {:?}", arg),
+
+                _ => {
+                    if options.generation_file.is_some() {
+                        panic!("Invalid number of arguments");
+                    }
+                    options.generation_file = Some(arg.clone());
+                }
+            }
+        }
+
+        match (&mut options.brain_kind, state_size) {
+            (BrainKind::Recurrent(size), Some(state_size)) => {
+                if state_size == 0 {
+                    panic!("The state size must be positive");
+                }
+                *size = state_size;
+            },
+            (_, Some(_)) => panic!("--state-size only applies to recurrent brains, use it with --brain recurrent"),
+            _ => (),
+        }
+
+        // The curriculum needs generated layouts
+        if options.curriculum && generator_kind.is_none() {
+            generator_kind = Some(GeneratorKind::Obstacles);
+        }
+
+        options.generator = generator_kind.map(|kind| Generator::new(kind, density, seed));
+
+        options.corpse_rules = corpse_ratio.map(|ratio| CorpseRules {
+            ratio,
+            decay_steps: corpse_decay,
+        });
+
+        // Sampling only applies to the evolutionary training, snakes always take their best choice otherwise
+        let ignores_sampling = options.export.is_some()
+            || options.benchmark_episodes.is_some()
+            || options.reinforcement
+            || options.imitation_dataset.is_some()
+            || options.record_demonstrations.is_some();
+        if options.policy.temperature.is_some() && ignores_sampling {
+            panic!("--sample only applies to the evolutionary training, not to exports, --benchmark, --dqn, --imitate or --record-demos");
+        }
+
+        options
+    }
+
+}
+
+
+/// Parses comma separated weights, in the order of `Item::ALL`
+fn parse_item_weights(value: &str) -> ItemWeights {
+
+    let weights: Vec<f64> = value.split(',')
+        .map(|weight| weight.trim().parse().unwrap_or_else(
+            |_| panic!("Invalid item weight: {:?}", weight)
+        ))
+        .collect();
+
+    let weights: ItemWeights = weights.try_into().unwrap_or_else(
+        |_| panic!("Expected {} item weights, in the order {:?}", Item::ALL.len(), Item::ALL)
+    );
+
+    if weights.iter().any(|weight| *weight < 0.0) || weights.iter().sum::<f64>() <= 0.0 {
+        panic!("Item weights must be non-negative and not all zero");
+    }
+
+    weights
+}
@@ -2,6 +2,9 @@ use std::time::Duration;
 use graphics::types::Color;
 use lazy_static::lazy_static;
 
+use crate::generator::CurriculumStage;
+use crate::map::ItemWeights;
+
 
 // Map constants
 
@@ -9,6 +12,39 @@ pub const MAP_WIDTH: f64 = TOPBAR_HEIGHT;
 pub const MAP_HEIGHT: usize = 75;
 pub const WORLD_WIDTH: usize = 90;
 pub const BLOCK_SIZE: f64 = 8.0;
+pub const MAX_APPLE_SPAWN_ATTEMPTS: usize = 1000;
+
+// Built-in layouts constants
+
+pub const PILLAR_SIZE: usize = 3;
+pub const PILLAR_SPACING: usize = 12;
+pub const CORRIDOR_WIDTH: usize = 8;
+/// The size of the gap at the end of each corridor wall
+pub const CORRIDOR_OPENING: usize = 8;
+pub const MAZE_PASSAGE_WIDTH: usize = 5;
+pub const BUILT_IN_MAZE_SEED: u64 = 42;
+pub const DEFAULT_LAYOUT: &str = "box";
+
+// Procedural generation constants
+
+pub const DEFAULT_GENERATOR_DENSITY: f64 = 0.1;
+/// Denser maps rarely leave room for all the snakes
+pub const MAX_GENERATOR_DENSITY: f64 = 0.6;
+/// How much the density is lowered each time a generated map is too crowded for the snakes
+pub const GENERATOR_DENSITY_RETRY_STEP: f64 = 0.05;
+pub const MAX_OBSTACLE_SIZE: usize = 6;
+pub const CAVE_SMOOTHING_ITERATIONS: usize = 4;
+/// The number of walls in a 3x3 area needed for its center to become a wall
+pub const CAVE_WALL_THRESHOLD: usize = 5;
+
+/// The stages of the curriculum, from the easiest to the hardest
+pub const CURRICULUM: [CurriculumStage; 5] = [
+    CurriculumStage { fitness_threshold: 1.0, density: 0.0, arena_margin: 0 },
+    CurriculumStage { fitness_threshold: 2.0, density: 0.05, arena_margin: 0 },
+    CurriculumStage { fitness_threshold: 3.0, density: 0.1, arena_margin: 4 },
+    CurriculumStage { fitness_threshold: 4.0, density: 0.15, arena_margin: 8 },
+    CurriculumStage { fitness_threshold: f64::INFINITY, density: 0.2, arena_margin: 12 },
+];
 
 lazy_static! {
 
@@ -35,10 +71,31 @@ pub const FONT_SIZE: u32 = 24;
 
 pub const TOPBAR_HEIGHT: f64 = 50.0;
 
+/// Zoom levels cycled through when zooming on the selected snake
+pub const ZOOM_LEVELS: [f64; 3] = [1.0, 2.0, 4.0];
+pub const OUTLINE_RADIUS: f64 = 0.5;
+
 pub const WIN_WIDTH: f64 = WORLD_WIDTH as f64 * BLOCK_SIZE;
 pub const WIN_HEIGHT: f64 = TOPBAR_HEIGHT + MAP_HEIGHT as f64 * BLOCK_SIZE;
 
 
+// Terminal constants
+
+pub const TUI_FRAMES_PER_SECOND: f64 = 30.0;
+/// The number of terminal lines above the map
+pub const TUI_TOPBAR_LINES: usize = 2;
+
+
+// Export constants
+
+/// The size in pixels of a block in exported images
+pub const EXPORT_BLOCK_SIZE: usize = 4;
+/// Speed of the GIF color quantization, from 1 (best quality) to 30 (fastest)
+pub const GIF_ENCODING_SPEED: i32 = 10;
+/// The number of steps simulated before exporting, if not specified
+pub const DEFAULT_EXPORT_STEPS: usize = 500;
+
+
 // Colors
 
 pub const BACKGROUND_COLOR: Color = [0.0, 0.0, 0.0, 1.0];
@@ -47,7 +104,16 @@ pub const TAIL_COLOR: Color = [0.7, 0.8, 0.2, 1.0];
 pub const WALL_COLOR: Color = [0.8, 0.8, 0.8, 1.0];
 pub const VOID_COLOR: Color = BACKGROUND_COLOR;
 pub const APPLE_COLOR: Color = [1.0, 0.0, 0.0, 1.0];
+pub const GOLDEN_APPLE_COLOR: Color = [1.0, 0.84, 0.0, 1.0];
+pub const POISON_COLOR: Color = [0.6, 0.0, 0.8, 1.0];
+pub const SHRINK_PILL_COLOR: Color = [0.0, 0.8, 0.8, 1.0];
 pub const TEXT_COLOR: Color = [1.0, 1.0, 1.0, 1.0];
+/// How much lighter the head of a snake is compared to its body
+pub const HEAD_LIGHTEN: f32 = 0.5;
+pub const TAIL_SATURATION: f64 = 0.8;
+pub const TAIL_VALUE: f64 = 0.9;
+pub const SELECTED_COLOR: Color = [0.2, 0.6, 1.0, 1.0];
+pub const SIGHT_OUTLINE_COLOR: Color = [0.2, 0.6, 1.0, 0.8];
 
 
 // Game constants
@@ -59,9 +125,25 @@ pub const UPDATE_DEALY: f64 = 1.0 / STEPS_PER_SECOND;
 
 pub const NEXT_GENERATION_DELAY: Duration = Duration::from_millis(0);
 
+// Item constants
+
+/// Relative spawn chances of apples, golden apples, poison and shrinking pills
+/// Only apples spawn by default, the other items are opted into with `--item-weights`
+pub const DEFAULT_ITEM_WEIGHTS: ItemWeights = [1.0, 0.0, 0.0, 0.0];
+pub const GOLDEN_APPLE_GROWTH: i64 = 3;
+pub const POISON_DAMAGE: i64 = 3;
+pub const SHRINK_PILL_AMOUNT: i64 = 2;
+/// The number of steps corpse apples last, if not specified
+pub const DEFAULT_CORPSE_DECAY: usize = 200;
+/// The fitness awarded for each kill in the arena mode, if not specified
+pub const DEFAULT_KILL_REWARD: f64 = 2.0;
+
 // Snake constants
 
 pub const INITIAL_SNAKE_LENGTH: usize = 5;
+pub const MIN_SNAKE_LENGTH: usize = 2;
+/// The maximum hue difference between a parent and its offspring
+pub const LINEAGE_HUE_SHIFT: f64 = 0.03;
 
 
 // AI agent constants
@@ -72,11 +154,116 @@ pub const SIGHT_SIZE: usize = 1 + SIGHT_RADIUS * 2;
 /// Updated automatically
 pub const SIGHT_INPUT_SIZE: usize = SIGHT_SIZE * SIGHT_SIZE;
 
+/// The number of independent sums the dot products of the brains are split into
+/// A single running sum makes every addition wait for the previous one, several let them run in parallel
+pub const DOT_LANES: usize = 8;
+
 pub const GENERATION_SIZE: usize = 25;
-pub const MAX_APPLES: usize = 50;
+pub const MAX_ITEMS: usize = 50;
 
 pub const MUTATION_CHANCE: f64 = 0.8;
 pub const MAX_MUTATION: f64 = 0.5;
+/// The lowest fraction of the initial mutation chance and step the schedules anneal down to
+pub const MIN_MUTATION_FACTOR: f64 = 0.1;
+/// The number of generations the linear schedule takes to reach its lowest mutation
+pub const MUTATION_ANNEALING_GENERATIONS: usize = 200;
+/// The factor the exponential schedule multiplies the mutation by at every generation
+pub const MUTATION_DECAY: f64 = 0.98;
+/// How fast the self-adaptive step sizes change, as the standard deviation of their log-normal mutation
+pub const SELF_ADAPTATION_RATE: f64 = 0.2;
+pub const MIN_STEP_SIZE: f64 = 0.001;
+pub const MAX_STEP_SIZE: f64 = 2.0;
 
 pub const GENERATION_CARRYOVER: usize = 5;
 
+
+// NEAT constants
+
+/// The chance of replacing a connection weight with a new random one, instead of perturbing it
+pub const NEAT_WEIGHT_RESET_CHANCE: f64 = 0.1;
+pub const NEAT_ADD_CONNECTION_CHANCE: f64 = 0.3;
+pub const NEAT_ADD_NODE_CHANCE: f64 = 0.1;
+/// The number of random node pairs tried when adding a connection
+pub const NEAT_ADD_CONNECTION_ATTEMPTS: usize = 20;
+pub const NEAT_EXCESS_COEFFICIENT: f64 = 1.0;
+pub const NEAT_DISJOINT_COEFFICIENT: f64 = 1.0;
+pub const NEAT_WEIGHT_COEFFICIENT: f64 = 0.4;
+/// Genomes closer than this distance belong to the same species
+pub const NEAT_COMPATIBILITY_THRESHOLD: f64 = 3.0;
+
+
+// Recurrent brain constants
+
+/// The size of the hidden state of recurrent brains, if not specified
+pub const DEFAULT_RECURRENT_STATE_SIZE: usize = 16;
+
+
+// Speciation constants
+
+/// Dense or recurrent brains whose weights differ by less than this on average belong to the same species
+pub const DENSE_COMPATIBILITY_THRESHOLD: f64 = 0.5;
+
+
+// Novelty search constants
+
+/// The number of cells along each side of the grid used to describe where a snake went
+pub const BEHAVIOR_GRID_SIZE: usize = 4;
+/// The number of nearest behaviors a behavior is compared with
+pub const NOVELTY_NEIGHBOURS: usize = 10;
+/// The number of most novel behaviors archived at each generation
+pub const NOVELTY_ARCHIVE_ADDITIONS: usize = 2;
+/// The oldest archived behaviors are forgotten past this size
+pub const MAX_NOVELTY_ARCHIVE_SIZE: usize = 500;
+
+
+// Island model constants
+
+/// The number of generations between migrations, if not specified
+pub const DEFAULT_MIGRATION_INTERVAL: usize = 5;
+/// The number of brains each island sends to each of its neighbours, if not specified
+pub const DEFAULT_MIGRATION_SIZE: usize = 2;
+/// The number of generations the islands evolve for, if not specified
+pub const DEFAULT_ISLAND_GENERATIONS: usize = 50;
+/// Generations still running after this many steps are ended, since snakes can loop forever
+pub const MAX_GENERATION_STEPS: usize = 5000;
+
+
+// Evolution strategy constants
+
+/// The standard deviation of the perturbations of the parameters
+pub const ES_NOISE_STD: f64 = 0.05;
+pub const ES_LEARNING_RATE: f64 = 0.03;
+
+
+// Imitation learning constants
+
+pub const IMITATION_LEARNING_RATE: f64 = 0.0005;
+/// The number of passes over the demonstrations, if not specified
+pub const DEFAULT_IMITATION_EPOCHS: usize = 5;
+
+
+
+// Reinforcement learning constants
+
+/// The reward for each point of fitness earned by a move
+pub const RL_FITNESS_REWARD: f64 = 1.0;
+pub const RL_DEATH_REWARD: f64 = -1.0;
+/// The reward of every move, negative so that the snakes don't wander
+pub const RL_STEP_REWARD: f64 = -0.01;
+/// How much future rewards are worth compared to immediate ones
+pub const RL_DISCOUNT: f64 = 0.95;
+pub const RL_LEARNING_RATE: f64 = 0.0001;
+/// The number of past moves remembered, the oldest ones being forgotten first
+pub const RL_REPLAY_CAPACITY: usize = 50_000;
+/// The number of past moves learned from at each step
+pub const RL_BATCH_SIZE: usize = 32;
+/// The number of training steps between updates of the target brain
+pub const RL_TARGET_UPDATE_INTERVAL: usize = 500;
+pub const RL_START_EPSILON: f64 = 1.0;
+pub const RL_MIN_EPSILON: f64 = 0.05;
+/// The number of moves, counted over all the snakes, over which the chance of moving randomly decreases to its minimum
+pub const RL_EPSILON_DECAY_MOVES: usize = 50_000;
+/// The maximum length of an episode, shorter than `MAX_GENERATION_STEPS` so that training moves on to fresh maps often
+pub const RL_MAX_EPISODE_STEPS: usize = 1000;
+/// The number of episodes between saved checkpoints
+pub const RL_CHECKPOINT_INTERVAL: usize = 10;
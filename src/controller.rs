@@ -0,0 +1,169 @@
+use crate::consts::*;
+use crate::map::{Block, Location, Map};
+use crate::pathfinding::{self, is_safe, is_target};
+use crate::policy::Policy;
+use crate::snake::{Direction, Snake};
+
+
+/// What chooses the direction a snake moves in
+/// Scripted controllers give a reference point to compare evolved brains with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Controller {
+
+    /// The snake's own brain, looking at its sight
+    Brain,
+    /// Moves to the safe neighbour closest to the nearest food, without looking further ahead
+    Greedy,
+    /// Follows the shortest safe path to the closest food
+    ShortestPath,
+    /// Follows a cycle going through every block inside the border of the map
+    /// Only fits maps without walls inside their border
+    Hamiltonian,
+
+}
+
+
+impl Controller {
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "greedy" => Controller::Greedy,
+            "path" => Controller::ShortestPath,
+            "cycle" => Controller::Hamiltonian,
+            _ => panic!("Unknown controller: {:?}. Expected \"greedy\", \"path\" or \"cycle\"", name),
+        }
+    }
+
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Controller::Brain => "brain",
+            Controller::Greedy => "greedy",
+            Controller::ShortestPath => "path",
+            Controller::Hamiltonian => "cycle",
+        }
+    }
+
+
+    /// The scripted controllers, in increasing order of sophistication
+    pub const SCRIPTED: [Controller; 3] = [Controller::Greedy, Controller::ShortestPath, Controller::Hamiltonian];
+
+
+    /// Whether the controller can drive a snake on the given map
+    pub fn fits(&self, map: &Map) -> bool {
+        match self {
+            Controller::Hamiltonian => has_interior_cycle(map),
+            _ => true,
+        }
+    }
+
+
+    /// Chooses the direction of a snake, turning the outputs of brains into a direction with the policy
+    /// Scripted controllers keep the current direction if there is no safe move
+    pub fn choose(&self, snake: &mut Snake, map: &Map, policy: &Policy) -> Direction {
+
+        let head = snake.bits[0];
+
+        match self {
+            Controller::Brain => return snake.think(map, policy),
+            Controller::Greedy => greedy_direction(map, head),
+            Controller::ShortestPath => pathfinding::direction_to_food(map, head),
+            Controller::Hamiltonian => cycle_direction(map, head),
+        }.unwrap_or(snake.direction())
+    }
+
+}
+
+
+fn manhattan_distance(a: Location, b: Location) -> usize {
+    a.x.abs_diff(b.x) + a.y.abs_diff(b.y)
+}
+
+
+/// The safe move bringing the head closest to the nearest food, ignoring what lies beyond
+/// Any safe move is taken if there is no food on the map
+fn greedy_direction(map: &Map, head: Location) -> Option<Direction> {
+
+    let food = (0..MAP_HEIGHT)
+        .flat_map(|y| (0..WORLD_WIDTH).map(move |x| Location::new(x, y)))
+        .filter(|location| is_target(map.get(*location)))
+        .min_by_key(|location| manhattan_distance(head, *location));
+
+    let mut moves = Direction::ALL.iter().filter_map(|direction| {
+        map.trans(head, 1, *direction)
+            .filter(|next| is_safe(map.get(*next)))
+            .map(|next| (*direction, next))
+    });
+
+    match food {
+        Some(food) => moves.min_by_key(|(_, next)| manhattan_distance(*next, food)),
+        None => moves.next(),
+    }.map(|(direction, _)| direction)
+}
+
+
+/// The size of the grid inside the border of the map, which the cycle goes through
+const INTERIOR_WIDTH: usize = WORLD_WIDTH - 2;
+const INTERIOR_HEIGHT: usize = MAP_HEIGHT - 2;
+
+
+/// Whether the cycle exists on the map: the inside of its border must be free of walls,
+/// and have an even number of rows or columns
+fn has_interior_cycle(map: &Map) -> bool {
+
+    if !INTERIOR_WIDTH.is_multiple_of(2) && !INTERIOR_HEIGHT.is_multiple_of(2) {
+        return false;
+    }
+
+    (1..MAP_HEIGHT - 1).all(|y| (1..WORLD_WIDTH - 1).all(|x| map.get(Location::new(x, y)) != Block::Wall))
+}
+
+
+/// The next move along a fixed Hamiltonian cycle of the inside of the map's border
+/// Once the body lies along the cycle, the snake never runs into itself. The shortest path is followed
+/// instead whenever the next block isn't safe, such as when another snake is in the way, and while
+/// the head is on the border of a wrapping map
+fn cycle_direction(map: &Map, head: Location) -> Option<Direction> {
+
+    let inside = (1..WORLD_WIDTH - 1).contains(&head.x) && (1..MAP_HEIGHT - 1).contains(&head.y);
+    if !inside {
+        return pathfinding::direction_to_food(map, head);
+    }
+
+    let (x, y) = (head.x - 1, head.y - 1);
+
+    let direction = if INTERIOR_HEIGHT.is_multiple_of(2) {
+        row_cycle_direction(x, y, INTERIOR_WIDTH, INTERIOR_HEIGHT)
+    } else {
+        // Same cycle with the rows and columns swapped
+        match row_cycle_direction(y, x, INTERIOR_HEIGHT, INTERIOR_WIDTH) {
+            Direction::Up => Direction::Left,
+            Direction::Down => Direction::Right,
+            Direction::Left => Direction::Up,
+            Direction::Right => Direction::Down,
+        }
+    };
+
+    match map.trans(head, 1, direction) {
+        Some(next) if is_safe(map.get(next)) => Some(direction),
+        _ => pathfinding::direction_to_food(map, head),
+    }
+}
+
+
+/// The direction of a cycle that zigzags through the rows of a grid with an even number of rows,
+/// leaving out the first column, which leads back to the start
+fn row_cycle_direction(x: usize, y: usize, width: usize, height: usize) -> Direction {
+
+    if x == 0 {
+        return if y == 0 { Direction::Right } else { Direction::Up };
+    }
+
+    if y.is_multiple_of(2) {
+        if x == width - 1 { Direction::Down } else { Direction::Right }
+    } else if x == 1 {
+        if y == height - 1 { Direction::Left } else { Direction::Down }
+    } else {
+        Direction::Left
+    }
+}
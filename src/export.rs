@@ -0,0 +1,86 @@
+use std::fs::File;
+
+use graphics::types::Color;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, Rgba, RgbaImage};
+
+use crate::game_manager::GameManager;
+use crate::map::{Location, Map};
+use crate::consts::*;
+
+
+fn to_pixel(color: Color) -> Rgba<u8> {
+    Rgba([
+        (color[0] * 255.0) as u8,
+        (color[1] * 255.0) as u8,
+        (color[2] * 255.0) as u8,
+        (color[3] * 255.0) as u8,
+    ])
+}
+
+
+/// Software rasterizer for the map
+/// Each block is drawn as a square of `EXPORT_BLOCK_SIZE` pixels, with the same colors as the window
+pub fn render_map(map: &Map) -> RgbaImage {
+
+    let mut image = RgbaImage::new(
+        (WORLD_WIDTH * EXPORT_BLOCK_SIZE) as u32,
+        (MAP_HEIGHT * EXPORT_BLOCK_SIZE) as u32
+    );
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let location = Location::new(
+            x as usize / EXPORT_BLOCK_SIZE,
+            y as usize / EXPORT_BLOCK_SIZE
+        );
+        *pixel = to_pixel(map.color_at(location));
+    }
+
+    image
+}
+
+
+/// Runs the game headless for the given number of steps, then saves a snapshot of the map as PNG
+pub fn export_png(mut game_manager: GameManager, path: &str, steps: usize) {
+
+    for _ in 0..steps {
+        game_manager.step();
+    }
+
+    render_map(game_manager.map()).save(path).expect(
+        "Failed to save the PNG image"
+    );
+
+    println!("Map snapshot after {} steps saved to file \"{}\"", steps, path);
+}
+
+
+/// Runs the game headless for the given number of steps, recording every step as a frame of an animated GIF
+pub fn record_gif(mut game_manager: GameManager, path: &str, steps: usize) {
+
+    let file = File::create(path).expect(
+        "Failed to create the GIF file"
+    );
+
+    let mut encoder = GifEncoder::new_with_speed(file, GIF_ENCODING_SPEED);
+    encoder.set_repeat(Repeat::Infinite).expect(
+        "Failed to set the GIF repetition"
+    );
+
+    // Play the animation at the same speed as the game
+    let delay = Delay::from_numer_denom_ms(1000, STEPS_PER_SECOND as u32);
+
+    for step in 0..=steps {
+
+        if step > 0 {
+            game_manager.step();
+        }
+
+        let frame = Frame::from_parts(render_map(game_manager.map()), 0, 0, delay);
+        encoder.encode_frame(frame).expect(
+            "Failed to encode a GIF frame"
+        );
+    }
+
+    println!("Recording of {} steps saved to file \"{}\"", steps, path);
+}
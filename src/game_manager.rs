@@ -1,16 +1,27 @@
 
-use opengl_graphics::TextureSettings;
 use piston_window::{Glyphs, PistonWindow};
 
-use crate::brain::Brain;
-use crate::render::{render_text, WindowCoordinates, clear_screen};
-use crate::snake::Snake;
-use crate::map::{Map, Location};
+use crate::brain::{Brain, BrainKind, DenseBrain};
+use graphics::Context;
+use opengl_graphics::GlGraphics;
+
+use crate::render::{render_text, render_block, render_outline, WindowCoordinates, Camera, clear_screen, clear_topbar};
+use crate::snake::{DeathCause, Direction, Snake};
+use crate::map::{Map, Block, Location, Item, ItemWeights, CorpseRules};
+use crate::layout::Layout;
+use crate::generator::{Curriculum, Generator};
+use crate::resolution::{self, Outcome};
+use crate::species;
+use crate::novelty::NoveltySearch;
+use crate::mutation::MutationConfig;
+use crate::imitation::Demonstration;
+use crate::controller::Controller;
+use crate::policy::Policy;
 use crate::render::Drawable;
-use crate::{consts::*, font_path};
+use crate::consts::*;
 
 
-enum GameStatus {
+pub enum GameStatus {
 
     Running,
     Paused,
@@ -18,20 +29,96 @@ enum GameStatus {
 }
 
 
+/// Statistics of a finished generation
+#[derive(Clone, Copy)]
+pub struct GenerationStats {
+
+    pub generation: usize,
+    pub best_fitness: f64,
+    pub mean_fitness: f64,
+    pub species: usize,
+
+}
+
+
 pub struct GameManager {
 
     game_status: GameStatus,
     map: Map,
+    layout: Layout,
+    /// Generates a new layout for each generation, if set
+    generator: Option<Generator>,
+    /// Makes the generated layouts harder as the snakes improve, if set
+    curriculum: Option<Curriculum>,
+    /// Whether the maps are toroidal
+    wrap: bool,
+    /// Relative spawn chances of the items
+    item_weights: ItemWeights,
+    /// If set, dead snakes leave apples behind
+    corpse_rules: Option<CorpseRules>,
+    /// Fitness awarded for each snake killed. Positive in the competitive arena mode
+    kill_reward: f64,
+    /// The number of teams the snakes are split into. 0 if the snakes play alone
+    teams: usize,
+    /// The kind of brain new random snakes are spawned with
+    brain_kind: BrainKind,
+    /// The size of each species of the last generation, indexed by species
+    species_sizes: Vec<usize>,
+    /// Blends the novelty of the snakes' behavior into the selection, if set
+    novelty_search: Option<NoveltySearch>,
+    mutation_config: MutationConfig,
+    /// Brains of the fittest snakes of the last generation, best first
+    elites: Vec<Brain>,
+    last_stats: Option<GenerationStats>,
+    /// What chooses the direction of the snakes of the generation
+    controller: Controller,
+    /// How the brains' outputs are turned into directions
+    policy: Policy,
+    /// Scripted snakes playing alongside the generation, which take no part in the selection
+    bots: Vec<Controller>,
+    /// What the snakes saw and did, if recording
+    demonstrations: Option<Vec<Demonstration>>,
+    /// Whether to keep the progress of the generations from being printed
+    quiet: bool,
     last_update: f64,
-    font: Glyphs,
     generation_count: usize,
     snakes: Vec<Snake>,
+    /// Index of the snake the user is focusing on, if any
+    selected: Option<usize>,
+    /// Whether the camera should follow the selected snake
+    follow: bool,
+    camera: Camera,
+    /// Last known position of the mouse cursor on the window
+    cursor: WindowCoordinates,
+
+}
 
+
+/// Saves the brains of a generation to a file named after the generation and the current time
+pub fn save_generation_file(brains: &[&Brain], generation: usize) {
+    use std::fs::File;
+    use std::io::Write;
+
+    let file_name = format!("Gen_{}_{}.gen.json", generation, chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"));
+
+    let mut file = File::create(&file_name).expect(
+        "Failed to create generation file"
+    );
+
+    let json = serde_json::to_string_pretty(&brains).expect(
+        "Failed to serialize the brains"
+    );
+
+    file.write_all(json.as_bytes()).expect(
+        "Failed to write to generation file"
+    );
+
+    println!("Generation {} saved to file \"{}\"", generation, file_name);
 }
 
 
 /// Determines where to spawn a snake taking into account the other snakes
-/// Returns the location where the snake should spawn
+/// Returns the preferred location where the snake should spawn, regardless of the layout
 fn determine_snake_spawn_location(index: usize) -> Location {
     
     let x: usize = ((index as f64 % *GRID_SIZE) * *SECTION_SIZE_X + *SECTION_SIZE_X / 2.0) as usize;
@@ -41,141 +128,607 @@ fn determine_snake_spawn_location(index: usize) -> Location {
 }
 
 
+/// Finds where the given number of snakes spawn on a map with the given layout, one after the other
+/// Around each head, the blocks its body could take in any direction are reserved, so that the snakes
+/// spawned later fit whichever directions the earlier ones face
+/// Returns None if the map is too crowded for all of them
+fn plan_spawn_points(layout: &Layout, wrap: bool, count: usize) -> Option<Vec<Location>> {
+
+    let mut map = Map::from_layout(layout, wrap);
+    let mut spawn_points = Vec::with_capacity(count);
+
+    for index in 0..count {
+
+        let preferred = layout.spawn_points
+            .get(index)
+            .copied()
+            .unwrap_or_else(|| determine_snake_spawn_location(index));
+
+        let head = map.find_spawn_location(preferred)?;
+
+        for direction in Direction::ALL {
+            for i in 0..INITIAL_SNAKE_LENGTH {
+                if let Some(bit) = map.trans(head, i as i64, direction) {
+                    if map.get(bit) == Block::Void {
+                        map.set_tail_block(bit, index);
+                    }
+                }
+            }
+        }
+
+        spawn_points.push(head);
+    }
+
+    Some(spawn_points)
+}
+
+
 impl GameManager {
 
+    /// Returns the location where the snake with the given index should spawn
+    /// Uses the spawn points of the layout first, then falls back to spreading the snakes evenly
+    /// The location is moved to the closest free area if it's blocked
+    /// Generated layouts always have room, since their spawn points are planned when they are generated
+    fn spawn_location(&self, index: usize) -> Location {
+
+        let preferred = self.layout.spawn_points
+            .get(index)
+            .copied()
+            .unwrap_or_else(|| determine_snake_spawn_location(index));
+
+        self.map.find_spawn_location(preferred).expect(
+            "No room left on the map to spawn a snake, the layout is too crowded"
+        )
+    }
+
+
     fn internal_initialize(&mut self, initialize_snakes: bool) {
 
         self.game_status = GameStatus::Running;
 
-        self.map = Map::create_new();
+        self.map = self.new_map();
 
         if initialize_snakes {
 
-            let mut snakes = Vec::with_capacity(GENERATION_SIZE);
-
             for i in 0..GENERATION_SIZE {
-                snakes.push(Snake::spawn_new(
-                    determine_snake_spawn_location(i),
+                let snake = Snake::spawn_new(
+                    self.brain_kind,
+                    self.spawn_location(i),
                     &mut self.map
-                ));
+                );
+                self.add_snake(snake);
             }
 
+            self.spawn_bots();
+            self.spawn_items();
         }
 
-        for _ in 0..MAX_APPLES {
-            self.map.spawn_apple();
+    }
+
+
+    /// Generate the layouts procedurally instead of using a fixed one
+    /// If the curriculum is enabled, the generator's parameters are controlled by it
+    pub fn set_generator(&mut self, generator: Generator, use_curriculum: bool) {
+
+        self.generator = Some(generator);
+        self.curriculum = if use_curriculum {
+            Some(Curriculum::new())
+        } else {
+            None
+        };
+
+        self.update_layout();
+    }
+
+
+    /// Remove the border walls and connect the opposite edges of the map
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+
+    /// Set the relative spawn chances of the items
+    pub fn set_item_weights(&mut self, weights: ItemWeights) {
+        self.item_weights = weights;
+    }
+
+
+    /// Make dead snakes leave apples behind
+    pub fn set_corpse_rules(&mut self, rules: Option<CorpseRules>) {
+        self.corpse_rules = rules;
+    }
+
+
+    /// Reward snakes for killing others, making them compete in the arena
+    pub fn set_kill_reward(&mut self, kill_reward: f64) {
+        self.kill_reward = kill_reward;
+    }
+
+
+    /// Split the snakes into teams sharing their colors and their fitness
+    pub fn set_teams(&mut self, teams: usize) {
+        self.teams = teams.min(GENERATION_SIZE);
+    }
+
+
+    /// Set the kind of brain new random snakes are spawned with
+    pub fn set_brain_kind(&mut self, kind: BrainKind) {
+        self.brain_kind = kind;
+    }
+
+
+    /// Select the snakes by the novelty of their behavior as well as their fitness
+    pub fn set_novelty_search(&mut self, novelty_search: Option<NoveltySearch>) {
+        self.novelty_search = novelty_search;
+    }
+
+
+    /// Set how the offspring are mutated
+    pub fn set_mutation_config(&mut self, config: MutationConfig) {
+        self.mutation_config = config;
+    }
+
+
+    /// Changes what chooses the direction of the snakes of the generation, from now on
+    pub fn set_controller(&mut self, controller: Controller) {
+        self.controller = controller;
+        for snake in self.snakes.iter_mut().filter(|snake| !snake.bot) {
+            snake.controller = controller;
         }
+    }
+
 
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
     }
 
 
-    /// Initialize the game manager and the game
-    pub fn initialize(&mut self) {
-        self.internal_initialize(true);
+    /// Adds scripted snakes to every generation, to compare the brains with
+    /// Takes effect from the next generation
+    pub fn set_bots(&mut self, bots: Vec<Controller>) {
+        self.bots = bots;
     }
 
 
-    /// Save the current generation to a file
-    fn save_generation(&self) {
-        use std::fs::File;
-        use std::io::Write;
+    /// Drive all the snakes of the generation with the shortest path bot, and record what they see and do
+    pub fn start_recording(&mut self) {
+        self.demonstrations = Some(Vec::new());
+        self.set_controller(Controller::ShortestPath);
+    }
 
-        let file_name = format!("Gen_{}_{}.gen.json", self.generation_count, chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"));
 
-        let mut file = File::create(&file_name).expect(
-            "Failed to create generation file"
-        );
+    /// Returns the demonstrations recorded so far, and clears them
+    pub fn take_demonstrations(&mut self) -> Vec<Demonstration> {
+        self.demonstrations.as_mut().map(std::mem::take).unwrap_or_default()
+    }
 
-        let brains: Vec<&Brain> = self.snakes.iter().map(|x| &x.brain).collect();
 
-        let json = serde_json::to_string_pretty(&brains).expect(
-            "Failed to serialize the brains"
-        );
+    /// Stop printing the progress of the generations
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+
+    /// Adds a spawned snake to the generation, assigning it to a team if teams are enabled
+    /// Teams are assigned in turn, so that they all have the same size
+    fn add_snake(&mut self, mut snake: Snake) {
+
+        snake.controller = self.controller;
+
+        if self.teams > 0 {
+            let team = self.snakes.len() % self.teams;
+            snake.join_team(team, team as f64 / self.teams as f64, &mut self.map);
+        }
+
+        self.snakes.push(snake);
+    }
+
+
+    /// Spawns the bots after the snakes of the generation, so that they don't change their ids
+    fn spawn_bots(&mut self) {
+        for controller in self.bots.clone() {
+
+            if !controller.fits(&self.map) {
+                panic!(
+                    "The {} bot can't play on this map: it needs a map without walls inside its border",
+                    controller.name()
+                );
+            }
+
+            let bot = Snake::spawn_new(
+                self.brain_kind,
+                self.spawn_location(self.snakes.len()),
+                &mut self.map
+            );
+            self.add_snake(bot);
+
+            let bot = self.snakes.last_mut().unwrap();
+            bot.controller = controller;
+            bot.bot = true;
+        }
+    }
+
+
+    /// The snakes of the generation, without the bots spawned after them
+    fn population(&self) -> &[Snake] {
+        let count = self.snakes.iter().position(|snake| snake.bot).unwrap_or(self.snakes.len());
+        &self.snakes[..count]
+    }
+
+
+    /// Returns the mean fitness of the members of each team, indexed by team
+    /// Empty if the snakes play alone
+    fn team_fitness(&self) -> Vec<f64> {
+
+        if self.teams == 0 {
+            return Vec::new();
+        }
+
+        let mut totals = vec![0.0; self.teams];
+        let mut sizes = vec![0; self.teams];
+
+        for snake in self.snakes.iter() {
+            totals[snake.team] += snake.fitness(self.kill_reward);
+            sizes[snake.team] += 1;
+        }
+
+        totals.iter().zip(sizes.iter()).map(|(total, size)| total / (*size).max(1) as f64).collect()
+    }
+
+
+    /// Create a new map with the current layout and rules
+    fn new_map(&self) -> Map {
+        let mut map = Map::from_layout(&self.layout, self.wrap);
+        map.set_corpse_rules(self.corpse_rules);
+        map
+    }
+
+
+    /// Generate the layout for the current generation, if a generator is set
+    /// The spawn points of the snakes are planned on the layout, which is generated again with a lower density
+    /// until all the snakes fit
+    fn update_layout(&mut self) {
+
+        let generator = match &mut self.generator {
+            Some(generator) => generator,
+            None => return,
+        };
+
+        if let Some(curriculum) = &self.curriculum {
+            curriculum.apply(generator);
+        }
+
+        let mut generator = *generator;
+        let count = self.population().len().max(GENERATION_SIZE) + self.bots.len();
+
+        loop {
+
+            let mut layout = generator.generate(self.generation_count);
+
+            if let Some(spawn_points) = plan_spawn_points(&layout, self.wrap, count) {
+                layout.spawn_points = spawn_points;
+                self.layout = layout;
+                return;
+            }
+
+            if generator.density <= 0.0 {
+                panic!("No room to spawn {} snakes on the generated map, even without obstacles", count);
+            }
+
+            generator.density = (generator.density - GENERATOR_DENSITY_RETRY_STEP).max(0.0);
+            self.log(&format!("The generated map is too crowded for the snakes, retrying with a density of {:.2}", generator.density));
+        }
+    }
+
+
+    /// Advance the curriculum according to the fitness of the current generation
+    fn update_curriculum(&mut self) {
+
+        let curriculum = match &mut self.curriculum {
+            Some(curriculum) => curriculum,
+            None => return,
+        };
+
+        let kill_reward = self.kill_reward;
+        let mean_fitness = self.snakes.iter().map(|snake| snake.fitness(kill_reward)).sum::<f64>() / self.snakes.len().max(1) as f64;
+
+        if curriculum.update(mean_fitness) {
+            let stage = curriculum.stage();
+            self.log(&format!("Mean fitness {:.2} reached curriculum stage {}", mean_fitness, stage + 1));
+        }
+    }
 
-        file.write_all(json.as_bytes()).expect(
-            "Failed to write to generation file"
-        );
 
-        println!("Generation {} saved to file \"{}\"", self.generation_count, file_name);
+    /// Initialize the game manager and the game
+    pub fn initialize(&mut self) {
+        self.internal_initialize(true);
+    }
+
+
+    /// Save the current generation to a file
+    pub fn save_generation(&self) {
+        let brains: Vec<&Brain> = self.population().iter().map(|x| &x.brain).collect();
+        save_generation_file(&brains, self.generation_count);
     }
 
     
     /// Reset the game and the generation
     fn reset_all(&mut self) {
-        self.reset_game();
         self.generation_count = 1;
+        self.elites.clear();
+        if self.curriculum.is_some() {
+            self.curriculum = Some(Curriculum::new());
+        }
+        self.update_layout();
+        self.reset_game();
         self.snakes.clear();
         
         for i in 0..GENERATION_SIZE {
-            self.snakes.push(Snake::spawn_new(
-                determine_snake_spawn_location(i),
+            let snake = Snake::spawn_new(
+                self.brain_kind,
+                self.spawn_location(i),
                 &mut self.map
-            ));
+            );
+            self.add_snake(snake);
         }
+
+        self.spawn_bots();
+        self.spawn_items();
+    }
+
+
+    /// Groups the snakes of the current generation into species of similar brains
+    /// Stores the species of each snake and the size of each species
+    fn speciate(&mut self) {
+
+        let brains: Vec<&Brain> = self.snakes.iter().map(|snake| &snake.brain).collect();
+        let species = species::speciate(&brains);
+
+        self.species_sizes = vec![0; species.iter().max().map_or(0, |max| max + 1)];
+        for (snake, s) in self.snakes.iter_mut().zip(species.iter()) {
+            snake.species = *s;
+            self.species_sizes[*s] += 1;
+        }
+    }
+
+
+    /// Scores the novelty of the behavior of the snakes of the current generation
+    fn update_novelty(&mut self) {
+
+        let novelty_search = match &mut self.novelty_search {
+            Some(novelty_search) => novelty_search,
+            None => return,
+        };
+
+        let descriptors: Vec<Vec<f64>> = self.snakes.iter().map(|snake| snake.behavior.descriptor()).collect();
+        let novelty = novelty_search.evaluate(&descriptors);
+        let archive_size = novelty_search.archive_size();
+
+        for (snake, novelty) in self.snakes.iter_mut().zip(novelty) {
+            snake.novelty = novelty;
+        }
+
+        let mean_novelty = self.snakes.iter().map(|snake| snake.novelty).sum::<f64>() / self.snakes.len().max(1) as f64;
+        self.log(&format!("Mean novelty: {:.3}, archived behaviors: {}", mean_novelty, archive_size));
+    }
+
+
+    /// The fitness each snake is selected by: its own, or its team's if teams are enabled
+    /// With novelty search, the fitness and the novelty are both scaled by their maximum in the generation, then blended
+    fn selection_fitness(&self) -> Vec<f64> {
+
+        let team_fitness = self.team_fitness();
+
+        let fitness: Vec<f64> = self.snakes.iter().map(|snake| if team_fitness.is_empty() {
+            snake.fitness(self.kill_reward)
+        } else {
+            team_fitness[snake.team]
+        }).collect();
+
+        let novelty_search = match &self.novelty_search {
+            Some(novelty_search) => novelty_search,
+            None => return fitness,
+        };
+
+        let max_fitness = fitness.iter().copied().fold(0.0, f64::max);
+        let max_novelty = self.snakes.iter().map(|snake| snake.novelty).fold(0.0, f64::max);
+        let scale = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
+
+        fitness.iter().zip(self.snakes.iter()).map(|(fitness, snake)|
+            (1.0 - novelty_search.weight) * scale(*fitness, max_fitness)
+                + novelty_search.weight * scale(snake.novelty, max_novelty)
+        ).collect()
+    }
+
+
+    /// The selection fitness shared among the members of each species, so that a single species can't take over
+    fn shared_fitness(&self) -> Vec<f64> {
+        self.selection_fitness().iter().zip(self.snakes.iter())
+            .map(|(fitness, snake)| fitness / self.species_sizes[snake.species] as f64)
+            .collect()
     }
 
 
     /// Selects the best snakes among the current generation
-    /// Keeps the longest snakes and discards the short ones
+    /// Keeps the fittest snakes by shared fitness and discards the ones that didn't score
     /// Empties the snakes vector and returns the selected snakes
     fn select_best_snakes(&mut self) -> Vec<Snake> {
 
-        // Discard the short snakes
-        self.snakes.retain(|x| x.length() > INITIAL_SNAKE_LENGTH);
+        let kill_reward = self.kill_reward;
+        let shared_fitness = self.shared_fitness();
 
-        // Sort the snakes by length in descending order
-        self.snakes.sort_by(|a, b| b.length().cmp(&a.length()));
+        let mut ranked: Vec<(f64, Snake)> = shared_fitness.into_iter()
+            .zip(self.snakes.drain(..))
+            .filter(|(fitness, _)| *fitness > 0.0)
+            .collect();
 
-        // Keep only the longest snakes
-        self.snakes.truncate(GENERATION_CARRYOVER);
+        // Sort the snakes by fitness in descending order, using their own fitness to break ties
+        ranked.sort_by(|(a_shared, a), (b_shared, b)| b_shared.total_cmp(a_shared)
+            .then(b.fitness(kill_reward).total_cmp(&a.fitness(kill_reward)))
+        );
 
-        self.snakes.drain(..).collect()
+        // Keep only the fittest snakes
+        ranked.into_iter()
+            .take(GENERATION_CARRYOVER)
+            .map(|(_, snake)| snake)
+            .collect()
+    }
+
+
+    /// Chooses the parents of the given number of offspring
+    /// Each species gets a quota of offspring proportional to the sum of its members' shared fitness,
+    /// which are then bred in turn from its fittest members
+    fn select_parents(&self, offspring_count: usize) -> Vec<Snake> {
+
+        let selection_fitness = self.selection_fitness();
+        let shared_fitness = self.shared_fitness();
+
+        let mut species_fitness = vec![0.0; self.species_sizes.len()];
+        for (snake, fitness) in self.snakes.iter().zip(shared_fitness.iter()) {
+            species_fitness[snake.species] += fitness.max(0.0);
+        }
+
+        let total: f64 = species_fitness.iter().sum();
+        if total <= 0.0 {
+            return Vec::new();
+        }
+
+        // Round the quotas down, then hand the remaining offspring to the largest remainders
+        let exact: Vec<f64> = species_fitness.iter().map(|fitness| fitness / total * offspring_count as f64).collect();
+        let mut quotas: Vec<usize> = exact.iter().map(|quota| quota.floor() as usize).collect();
+
+        let mut by_remainder: Vec<usize> = (0..quotas.len()).collect();
+        by_remainder.sort_by(|a, b| (exact[*b] - exact[*b].floor()).total_cmp(&(exact[*a] - exact[*a].floor())));
+        let remaining = offspring_count - quotas.iter().sum::<usize>();
+        for species in by_remainder.into_iter().take(remaining) {
+            quotas[species] += 1;
+        }
+
+        let mut parents = Vec::with_capacity(offspring_count);
+
+        for (species, quota) in quotas.into_iter().enumerate() {
+
+            let mut members: Vec<usize> = (0..self.snakes.len())
+                .filter(|i| self.snakes[*i].species == species && selection_fitness[*i] > 0.0)
+                .collect();
+            members.sort_by(|a, b| selection_fitness[*b].total_cmp(&selection_fitness[*a]));
+            members.truncate(GENERATION_CARRYOVER);
+
+            for i in members.into_iter().cycle().take(quota) {
+                parents.push(self.snakes[i].clone());
+            }
+        }
+
+        parents
+    }
+
+
+    /// Prints a progress message, unless the game manager is quiet
+    fn log(&self, message: &str) {
+        if !self.quiet {
+            println!("{}", message);
+        }
     }
 
 
     /// Pass to the next generation and reset the game
-    fn next_generation(&mut self) {
+    pub fn next_generation(&mut self) {
+
+        for bot in self.snakes.iter().skip(self.population().len()) {
+            self.log(&format!("{} bot fitness: {:.1}", bot.controller.name(), bot.fitness(self.kill_reward)));
+        }
+
+        // The bots take no part in the selection, and are spawned again with the new generation
+        self.snakes.truncate(self.population().len());
+
+        let fitness = self.fitness();
 
         // Increment the generation counter
         self.generation_count += 1;
 
-        println!("\nGeneration: {}\n", self.generation_count);
+        self.log(&format!("\nGeneration: {}\n", self.generation_count));
+
+        self.update_curriculum();
+        self.update_layout();
+
+        for (team, fitness) in self.team_fitness().iter().enumerate() {
+            self.log(&format!("Team {} fitness: {:.2}", team + 1, fitness));
+        }
+
+        self.update_novelty();
+
+        self.speciate();
+        self.log(&format!("Species: {}, sizes: {:?}", self.species_sizes.len(), self.species_sizes));
+
+        self.last_stats = Some(GenerationStats {
+            generation: self.generation_count - 1,
+            best_fitness: fitness.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            mean_fitness: fitness.iter().sum::<f64>() / fitness.len().max(1) as f64,
+            species: self.species_sizes.len(),
+        });
 
-        // Select the snakes to breed and repopulate the generation
-        let mut best_snakes = self.select_best_snakes();
+        // Select the snakes to carry over, then the parents of the rest of the generation
+        let elite_count = self.shared_fitness().iter()
+            .filter(|fitness| **fitness > 0.0)
+            .count()
+            .min(GENERATION_CARRYOVER);
+        let parents = self.select_parents(GENERATION_SIZE - elite_count);
+        let best_snakes = self.select_best_snakes();
 
-        println!("Good snakes in this generation: {}", best_snakes.len());
+        self.log(&format!("Good snakes in this generation: {}", best_snakes.len()));
         for (i, snake) in best_snakes.iter().enumerate() {
-            println!("{}. Snake length: {}", i+1, snake.length());
+            if self.kill_reward > 0.0 {
+                self.log(&format!("{}. Snake length: {}, kills: {}", i+1, snake.length(), snake.kills));
+            } else {
+                self.log(&format!("{}. Snake length: {}", i+1, snake.length()));
+            }
         }
-        println!();
+        self.log("");
+
+        self.elites = best_snakes.iter().map(|snake| snake.brain.clone()).collect();
+
+        // The new generation is spawned on a fresh map, so that snake ids match their index
+        self.reset_game();
 
         if best_snakes.is_empty() {
             // If there are no good snakes, repopulate the generation with new random snakes
             for _ in 0..GENERATION_SIZE {
-                self.snakes.push(
-                    Snake::spawn_new(
-                        determine_snake_spawn_location(self.snakes.len()),
-                        &mut self.map
-                    ));
+                let snake = Snake::spawn_new(
+                    self.brain_kind,
+                    self.spawn_location(self.snakes.len()),
+                    &mut self.map
+                );
+                self.add_snake(snake);
             }
 
         } else {
-            // If there are good snakes, repopulate the generation with offsprings of the best snakes
-            while self.snakes.len() < GENERATION_SIZE - best_snakes.len() {
-                for snake in best_snakes.iter() {
-                    self.snakes.push(snake.spawn_offspring(
-                        determine_snake_spawn_location(self.snakes.len()),
-                        &mut self.map
-                    ));
-                }
+            // If there are good snakes, repopulate the generation with offsprings of the parents of each species
+            let mutation = self.mutation_config.at(self.generation_count);
+            self.log(&format!("Mutation chance: {:.2}, step: {:.3}", mutation.chance, mutation.step));
+
+            for parent in parents.iter() {
+                let mut offspring = parent.spawn_offspring(
+                    &mutation,
+                    self.spawn_location(self.snakes.len()),
+                    &mut self.map
+                );
+                offspring.species = parent.species;
+                self.add_snake(offspring);
             }
 
             // Add the best snakes from the previous generation to the new generation
-            self.snakes.append(&mut best_snakes);
+            for snake in best_snakes.iter() {
+                let mut elite = snake.respawn(
+                    self.spawn_location(self.snakes.len()),
+                    &mut self.map
+                );
+                elite.species = snake.species;
+                self.add_snake(elite);
+            }
         }
 
-        self.reset_game();
+        self.spawn_bots();
+        self.spawn_items();
 
     }
 
@@ -183,11 +736,16 @@ impl GameManager {
     /// Reset game parameters and the map
     fn reset_game(&mut self) {
         self.last_update = 0.0;
-        self.map = Map::create_new();
+        self.deselect();
+        self.map = self.new_map();
+    }
+
 
-        // Respawn the apples
-        for _ in 0..MAX_APPLES {
-            self.map.spawn_apple();
+    /// Fill the map with items
+    /// Called after spawning the snakes, so that items don't get overwritten
+    fn spawn_items(&mut self) {
+        for _ in 0..MAX_ITEMS {
+            self.map.spawn_item(Item::random(&self.item_weights));
         }
     }
 
@@ -195,13 +753,13 @@ impl GameManager {
     pub fn initialize_from_file(&mut self, path: &str) {
 
         // Load the json file
-        let json = std::fs::read_to_string(path).expect(
-            format!("Failed to read the file: {:?}", path).as_str()
+        let json = std::fs::read_to_string(path).unwrap_or_else(
+            |_| panic!("Failed to read the file: {:?}", path)
         );
 
         // Deserialize the json file
-        let brains: Vec<Brain> = serde_json::from_str(&json).expect(
-            format!("Failed to deserialize the json file: {:?}", path).as_str()
+        let brains: Vec<Brain> = serde_json::from_str(&json).unwrap_or_else(
+            |_| panic!("Failed to deserialize the json file: {:?}", path)
         );
 
         // Initialize the game manager
@@ -209,35 +767,54 @@ impl GameManager {
 
         // Create the snakes from the brains
         for brain in brains {
-            self.snakes.push(Snake::spawn_with_brain(
+            brain.register_innovations();
+
+            let snake = Snake::spawn_with_brain(
                 brain,
-                determine_snake_spawn_location(self.snakes.len()),
+                self.spawn_location(self.snakes.len()),
                 &mut self.map
-            ));
+            );
+            self.add_snake(snake);
         }
 
-        println!("Loaded generation from file: {:?}", path)
+        self.spawn_bots();
+        self.spawn_items();
 
-    }
+        self.log(&format!("Loaded generation from file: {:?}", path));
 
+    }
 
-    pub fn new(window: &mut PistonWindow) -> Self {
-
-        let font = include_bytes!(font_path!());
-
-        let glyphs = Glyphs::from_bytes(
-            font,
-            window.create_texture_context(),
-            TextureSettings::new(),
-        ).unwrap();
 
+    pub fn new(layout: Layout) -> Self {
         Self {
             game_status: GameStatus::Running,
             snakes: Vec::new(),
             map: Map::empty_new(),
+            layout,
+            generator: None,
+            curriculum: None,
+            wrap: false,
+            item_weights: DEFAULT_ITEM_WEIGHTS,
+            corpse_rules: None,
+            kill_reward: 0.0,
+            teams: 0,
+            brain_kind: BrainKind::Dense,
+            species_sizes: Vec::new(),
+            novelty_search: None,
+            mutation_config: MutationConfig::new(),
+            elites: Vec::new(),
+            last_stats: None,
+            controller: Controller::Brain,
+            policy: Policy::new(),
+            bots: Vec::new(),
+            demonstrations: None,
+            quiet: false,
             last_update: 0.0,
-            font: glyphs,
             generation_count: 1,
+            selected: None,
+            follow: false,
+            camera: Camera::new(),
+            cursor: WindowCoordinates::new(0.0, 0.0),
         }
     }
 
@@ -271,19 +848,19 @@ impl GameManager {
             return;
         }
 
-        // Update the game elements
-        let mut population_count: usize = 0;
-        for snake in &mut self.snakes {
+        self.step();
 
-            if snake.alive {
-                population_count += 1;
-                snake.act(&mut self.map);
-            }
-            
-        }
+    }
+
+
+    /// Advance the game by a single step, regardless of the update rate
+    /// Used directly when running headless
+    pub fn step(&mut self) {
+
+        self.simulate_step();
 
         // Check if the game is over, if so, pass to the next generation
-        if population_count == 0 {
+        if self.population_count() == 0 {
             std::thread::sleep(NEXT_GENERATION_DELAY);
             self.next_generation();
         }
@@ -291,35 +868,372 @@ impl GameManager {
     }
 
 
+    /// Move all the snakes once, without passing to the next generation
+    fn simulate_step(&mut self) {
+
+        self.map.tick();
+
+        // Let all the snakes choose their move before any of them moves
+        let sights = self.demonstrations.is_some().then(|| self.observations());
+
+        let map = &self.map;
+        let policy = &self.policy;
+        let targets: Vec<Option<Option<Location>>> = self.snakes.iter_mut()
+            .map(|snake| snake.alive.then(|| snake.plan(map, policy)))
+            .collect();
+
+        if let (Some(demonstrations), Some(sights)) = (&mut self.demonstrations, sights) {
+            for (snake, sight) in self.snakes.iter().zip(sights) {
+                if let Some(input) = sight {
+                    demonstrations.push(Demonstration { input, direction: snake.direction() });
+                }
+            }
+        }
+
+        self.apply_moves(&targets);
+    }
+
+
+    /// Move all the snakes once in the given directions, indexed by snake id, without passing to the next generation
+    /// Snakes without a direction choose their own
+    pub fn step_with(&mut self, directions: &[Option<Direction>]) {
+
+        self.map.tick();
+
+        let map = &self.map;
+        let policy = &self.policy;
+        let targets: Vec<Option<Option<Location>>> = self.snakes.iter_mut()
+            .enumerate()
+            .map(|(i, snake)| snake.alive.then(|| match directions.get(i).copied().flatten() {
+                Some(direction) => snake.plan_direction(direction, map),
+                None => snake.plan(map, policy),
+            }))
+            .collect();
+
+        self.apply_moves(&targets);
+    }
+
+
+    /// Resolves the planned moves of the snakes against each other and applies them
+    fn apply_moves(&mut self, targets: &[Option<Option<Location>>]) {
+
+        let outcomes = resolution::resolve(&self.snakes, targets, &self.map);
+
+        // Apply the deaths first, then free the vacated tails, then move the heads
+        for (i, outcome) in outcomes.iter().enumerate() {
+            if let Outcome::Die(cause) = *outcome {
+                self.snakes[i].die(cause, &mut self.map);
+
+                // Credit the snake whose body was hit, unless it's a self collision or a teammate
+                if let DeathCause::Body(killer) = cause {
+                    if self.snakes[killer].team != self.snakes[i].team {
+                        self.snakes[killer].kills += 1;
+                    }
+                }
+            }
+        }
+
+        for (snake, outcome) in self.snakes.iter().zip(outcomes.iter()) {
+            if let Outcome::Move(_, entering) = *outcome {
+                snake.release_tail(entering, &mut self.map);
+            }
+        }
+
+        for (snake, outcome) in self.snakes.iter_mut().zip(outcomes.iter()) {
+            if let Outcome::Move(new_head, entering) = *outcome {
+                snake.advance_to(new_head, entering, &mut self.map);
+            }
+        }
+
+        self.update_camera();
+    }
+
+
+    /// Plays a new generation made of the given brains headless, until all the snakes die
+    /// or `MAX_GENERATION_STEPS` pass
+    /// Returns the fitness of each brain
+    pub fn evaluate(&mut self, brains: Vec<Brain>) -> Vec<f64> {
+
+        self.spawn_generation(brains);
+
+        for _ in 0..MAX_GENERATION_STEPS {
+            if self.population_count() == 0 {
+                break;
+            }
+            self.simulate_step();
+        }
+
+        self.fitness()
+    }
+
+
+    /// Starts a new generation made of the given brains, without breeding
+    pub fn spawn_generation(&mut self, brains: Vec<Brain>) {
+
+        self.generation_count += 1;
+        self.update_layout();
+        self.reset_game();
+        self.snakes.clear();
+
+        for brain in brains {
+            let snake = Snake::spawn_with_brain(
+                brain,
+                self.spawn_location(self.snakes.len()),
+                &mut self.map
+            );
+            self.add_snake(snake);
+        }
+
+        self.spawn_bots();
+        self.spawn_items();
+    }
+
+
+    /// The fitness of each snake of the current generation, indexed by snake id
+    pub fn fitness(&self) -> Vec<f64> {
+        self.population().iter().map(|snake| snake.fitness(self.kill_reward)).collect()
+    }
+
+
+    /// What each living snake of the generation sees, encoded as the input of a brain, indexed by snake id
+    pub fn observations(&self) -> Vec<Option<Vec<f64>>> {
+        self.population().iter().map(|snake| snake.alive.then(|| snake.sight(&self.map))).collect()
+    }
+
+
+    /// The brains of the snakes of the current generation
+    pub fn brains(&self) -> impl Iterator<Item = &Brain> {
+        self.population().iter().map(|snake| &snake.brain)
+    }
+
+
+    /// A copy of the first dense brain of the current generation, which trainers improving a single brain start from
+    /// Panics if there is none, naming the trainer that needs it
+    pub fn first_dense_brain(&self, trainer: &str) -> DenseBrain {
+        self.brains()
+            .find_map(|brain| match brain {
+                Brain::Dense(brain) => Some(brain.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("{} needs dense brains, but the generation has none", trainer))
+    }
+
+
     /// Handle user input
     pub fn handle_input(&mut self, args: &piston::ButtonArgs) {
-        use piston::input::Key;
+        use piston::input::{Button, MouseButton};
 
-        if args.state == piston::input::ButtonState::Press {
-            match args.button {
-                piston::input::Button::Keyboard(key) => {
-                    match key {
-                        
-                        Key::Space => match self.game_status {
-                            GameStatus::Running => self.pause(),
-                            GameStatus::Paused => self.unpause(),
-                        }
+        if args.state != piston::input::ButtonState::Press {
+            return;
+        }
 
-                        Key::Return => self.next_generation(),
+        match args.button {
+            Button::Keyboard(key) => self.handle_key(key),
 
-                        Key::R => self.reset_all(),
+            Button::Mouse(MouseButton::Left) => {
+                self.select_at_cursor();
+            },
+
+            // Unhandled button types
+            _ => {}
+        }
+    }
 
-                        Key::S => self.save_generation(),
-                        
-                        // Unhandled keys
-                        _ => {}
-                    }
-                },
 
-                // Unhandled button types
-                _ => {}
+    /// Handle a key press
+    /// Shared by all the frontends, so that the controls are the same everywhere
+    pub fn handle_key(&mut self, key: piston::input::Key) {
+        use piston::input::Key;
+
+        match key {
+            
+            Key::Space => match self.game_status {
+                GameStatus::Running => self.pause(),
+                GameStatus::Paused => self.unpause(),
             }
+
+            Key::Return => self.next_generation(),
+
+            Key::R => self.reset_all(),
+
+            Key::S => self.save_generation(),
+
+            Key::Tab => self.select_next(),
+
+            Key::F => {
+                self.follow = !self.follow;
+                self.update_camera();
+            },
+
+            Key::Z => {
+                self.cycle_zoom();
+                self.update_camera();
+            },
             
+            // Unhandled keys
+            _ => {}
+        }
+    }
+
+
+    /// Keep track of the mouse cursor position
+    pub fn handle_cursor(&mut self, position: [f64; 2]) {
+        self.cursor = WindowCoordinates::new(position[0], position[1]);
+    }
+
+
+    /// Select the alive snake under the mouse cursor
+    /// Deselects if there is no snake under the cursor
+    fn select_at_cursor(&mut self) {
+
+        let location = match self.camera.to_map_location(&self.cursor) {
+            Some(location) => location,
+            None => return,
+        };
+
+        // Snake ids are their index in the snakes vector
+        match self.map.get(location).owner() {
+            Some(id) => self.selected = Some(id),
+            None => self.deselect(),
+        }
+
+        self.update_camera();
+    }
+
+
+    /// Select the next alive snake after the currently selected one
+    fn select_next(&mut self) {
+
+        let start = self.selected.map_or(0, |index| index + 1);
+        let count = self.snakes.len();
+
+        self.selected = (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|&index| self.snakes[index].alive);
+
+        self.update_camera();
+    }
+
+
+    /// Remove the selection and reset the camera
+    fn deselect(&mut self) {
+        self.selected = None;
+        self.camera.reset();
+    }
+
+
+    /// Switch to the next zoom level
+    fn cycle_zoom(&mut self) {
+        let current = ZOOM_LEVELS.iter().position(|&zoom| zoom == self.camera.zoom).unwrap_or(0);
+        self.camera.zoom = ZOOM_LEVELS[(current + 1) % ZOOM_LEVELS.len()];
+    }
+
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+
+    pub fn generation_count(&self) -> usize {
+        self.generation_count
+    }
+
+
+    pub fn game_status(&self) -> &GameStatus {
+        &self.game_status
+    }
+
+
+    /// Statistics of the last finished generation, if any
+    pub fn last_stats(&self) -> Option<GenerationStats> {
+        self.last_stats
+    }
+
+
+    /// Brains of the fittest snakes of the last generation, best first
+    pub fn elite_brains(&self) -> &[Brain] {
+        &self.elites
+    }
+
+
+    /// Replaces the brains of the offspring of the current generation with the given brains
+    /// The snakes carried over from the last generation keep theirs
+    /// The immigrants start with an empty memory, like spawned snakes
+    pub fn immigrate(&mut self, brains: &[Brain]) {
+
+        let offspring_count = self.population().len().saturating_sub(self.elites.len());
+
+        for (snake, brain) in self.snakes.iter_mut().take(offspring_count).zip(brains.iter()) {
+            snake.brain = brain.clone();
+            snake.brain.reset_state();
+        }
+    }
+
+
+    /// The number of species the last generation was split into
+    pub fn species_count(&self) -> usize {
+        self.species_sizes.len().max(1)
+    }
+
+
+    /// The number of snakes still alive in the current generation
+    pub fn population_count(&self) -> usize {
+        self.population().iter().filter(|snake| snake.alive).count()
+    }
+
+
+    /// Describes the statistics of the selected snake, if any
+    pub fn selection_stats(&self) -> Option<String> {
+        self.selected_snake().map(|snake| {
+
+            let mut stats = format!(
+                "L: {}  Age: {}  Hunger: {}  Kills: {}  Fit: {:.1}",
+                snake.length(),
+                snake.age,
+                snake.hunger,
+                snake.kills,
+                snake.fitness(self.kill_reward)
+            );
+
+            if snake.controller != Controller::Brain {
+                stats += &format!("  Bot: {}", snake.controller.name());
+            }
+
+            if self.teams > 0 {
+                stats += &format!("  Team {}: {:.1}", snake.team + 1, self.team_fitness()[snake.team]);
+            }
+
+            stats
+        })
+    }
+
+
+    /// Returns the selected snake, if it's still alive
+    pub fn selected_snake(&self) -> Option<&Snake> {
+        self.selected
+            .and_then(|index| self.snakes.get(index))
+            .filter(|snake| snake.alive)
+    }
+
+
+    /// Move the camera according to the current selection
+    fn update_camera(&mut self) {
+
+        let head = self.selected_snake().map(|snake| snake.bits[0]);
+
+        match head {
+            Some(head) if self.follow => self.camera.follow(head),
+            Some(_) => {
+                // Keep the zoom level but show the whole map
+                let zoom = self.camera.zoom;
+                self.camera.reset();
+                self.camera.zoom = zoom;
+            },
+            None => {
+                // The selected snake died
+                self.selected = None;
+                self.camera.reset();
+            }
         }
     }
 
@@ -337,43 +1251,132 @@ impl GameManager {
 
 
     /// Draw the game on the screen
-    pub fn draw(&mut self, args: &piston::RenderArgs, gl: &mut opengl_graphics::GlGraphics, window: &mut PistonWindow, event: &piston::Event) {
+    pub fn draw(&self, args: &piston::RenderArgs, gl: &mut GlGraphics, window: &mut PistonWindow, event: &piston::Event, font: &mut Glyphs) {
 
         // Clear the screen
         clear_screen(gl);
 
-        // Draw the topbar
+        // Draw the game elements
+
+        self.map.draw(args, gl, window, event, &self.camera);
+
+        self.draw_selection(args, gl);
+
+        // Draw the topbar over the map, in case the map is zoomed
+        clear_topbar(args, gl);
         render_text(
-            &format!("Generation: {}", self.generation_count),
-            &mut self.font,
+            &format!("Generation: {}  Species: {}", self.generation_count, self.species_count()),
+            font,
             WindowCoordinates::new(FONT_SIZE as f64, (TOPBAR_HEIGHT + FONT_SIZE as f64) / 2.0),
             window,
             event
         );
 
-        // Draw the game elements
+        self.draw_selection_stats(window, event, font);
+
+        if let GameStatus::Paused = self.game_status {
+            let text = "Paused";
+            render_text(
+                text,
+                font,
+                WindowCoordinates::new(
+                    (WIN_WIDTH - (FONT_SIZE as f64 * text.len() as f64) / 2.0) / 2.0,
+                    (WIN_HEIGHT + FONT_SIZE as f64) / 2.0
+                ),
+                window,
+                event
+            );
+        }
+
+    }
+
 
-        self.map.draw(args, gl, window, event);
-
-        match self.game_status {
-            GameStatus::Paused => {
-                let text = "Paused";
-                render_text(
-                    text,
-                    &mut self.font,
-                    WindowCoordinates::new(
-                        (WIN_WIDTH - (FONT_SIZE as f64 * text.len() as f64) / 2.0) / 2.0,
-                        (WIN_HEIGHT + FONT_SIZE as f64) / 2.0
-                    ),
-                    window,
-                    event
+    /// Highlight the selected snake and outline its sight window
+    fn draw_selection(&self, args: &piston::RenderArgs, gl: &mut GlGraphics) {
+
+        let snake = match self.selected_snake() {
+            Some(snake) => snake,
+            None => return,
+        };
+
+        let camera = &self.camera;
+
+        gl.draw(args.viewport(), |mut context: Context, gl: &mut GlGraphics| {
+
+            context.transform = camera.transform(context.transform);
+
+            for bit in snake.bits.iter() {
+                render_block(
+                    SELECTED_COLOR,
+                    WindowCoordinates::from_map_location(*bit),
+                    &context,
+                    gl
                 );
-            },
-            _ => {}
-            
-        }        
+            }
+
+            // The sight window is centered on the head
+            let head = WindowCoordinates::from_map_location(snake.bits[0]);
+            render_outline(
+                SIGHT_OUTLINE_COLOR,
+                WindowCoordinates::new(
+                    head.x - SIGHT_RADIUS as f64 * BLOCK_SIZE,
+                    head.y - SIGHT_RADIUS as f64 * BLOCK_SIZE
+                ),
+                SIGHT_SIZE as f64 * BLOCK_SIZE,
+                &context,
+                gl
+            );
+
+        });
+    }
+
+
+    /// Show the statistics of the selected snake in the topbar
+    fn draw_selection_stats(&self, window: &mut PistonWindow, event: &piston::Event, font: &mut Glyphs) {
 
+        let text = match self.selection_stats() {
+            Some(text) => text,
+            None => return,
+        };
+
+        render_text(
+            &text,
+            font,
+            WindowCoordinates::new(WIN_WIDTH / 3.0, (TOPBAR_HEIGHT + FONT_SIZE as f64) / 2.0),
+            window,
+            event
+        );
     }
 
 }
 
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::generator::GeneratorKind;
+
+
+    /// Includes layouts that used to leave no room for the last snakes
+    const CAVES_TEST_SEED: u64 = 1000;
+    const CAVES_TEST_GENERATIONS: usize = 60;
+
+
+    #[test]
+    fn caves_at_maximum_density_leave_room_for_a_full_population() {
+
+        let mut game_manager = GameManager::new(Layout::boxed());
+        game_manager.set_quiet(true);
+        game_manager.set_generator(Generator::new(GeneratorKind::Caves, MAX_GENERATOR_DENSITY, CAVES_TEST_SEED), false);
+
+        let brains = vec![Brain::new(BrainKind::Dense); GENERATION_SIZE];
+
+        // Each generation has a new layout, some of which are too crowded at this density
+        for _ in 0..CAVES_TEST_GENERATIONS {
+            game_manager.spawn_generation(brains.clone());
+            assert_eq!(game_manager.population_count(), GENERATION_SIZE);
+        }
+    }
+
+}
@@ -0,0 +1,213 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::layout::Layout;
+use crate::map::Block;
+use crate::consts::*;
+
+
+/// The kind of procedurally generated layout
+#[derive(Clone, Copy)]
+pub enum GeneratorKind {
+
+    /// Randomly placed rectangular obstacles
+    Obstacles,
+    /// Cave-like walls smoothed with a cellular automaton
+    Caves,
+    /// A maze whose density is the chance of keeping the walls between cells
+    Maze,
+
+}
+
+
+impl GeneratorKind {
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "obstacles" => GeneratorKind::Obstacles,
+            "caves" => GeneratorKind::Caves,
+            "maze" => GeneratorKind::Maze,
+            _ => panic!("Unknown generator: {:?}. Expected \"obstacles\", \"caves\" or \"maze\"", name),
+        }
+    }
+
+}
+
+
+/// Generates a new random layout for each generation
+#[derive(Clone, Copy)]
+pub struct Generator {
+
+    pub kind: GeneratorKind,
+    /// The fraction of the arena covered by walls, in the range [0, 1]
+    pub density: f64,
+    /// The number of blocks filled with walls along each edge of the map
+    pub arena_margin: usize,
+    pub seed: u64,
+
+}
+
+
+impl Generator {
+
+    pub fn new(kind: GeneratorKind, density: f64, seed: u64) -> Self {
+        Generator {
+            kind,
+            density: density.clamp(0.0, MAX_GENERATOR_DENSITY),
+            arena_margin: 0,
+            seed,
+        }
+    }
+
+
+    /// Generates the layout of the given generation
+    /// The same seed and generation always produce the same layout
+    pub fn generate(&self, generation: usize) -> Layout {
+
+        let seed = self.seed.wrapping_add(generation as u64);
+
+        let mut layout = match self.kind {
+            GeneratorKind::Obstacles => self.obstacles(seed),
+            GeneratorKind::Caves => self.caves(seed),
+            GeneratorKind::Maze => Layout::maze(seed, 1.0 - self.density),
+        };
+
+        layout.shrink(self.arena_margin);
+
+        layout
+    }
+
+
+    /// Places random rectangles until the requested density is reached
+    fn obstacles(&self, seed: u64) -> Layout {
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut layout = Layout::boxed();
+
+        let area = (WORLD_WIDTH - 2) * (MAP_HEIGHT - 2);
+        let target = (area as f64 * self.density) as usize;
+        let mut walls = 0;
+
+        while walls < target {
+
+            let width = rng.gen_range(1..=MAX_OBSTACLE_SIZE);
+            let height = rng.gen_range(1..=MAX_OBSTACLE_SIZE);
+            let x = rng.gen_range(1..WORLD_WIDTH - width);
+            let y = rng.gen_range(1..MAP_HEIGHT - height);
+
+            for row in layout.blocks.iter_mut().skip(y).take(height) {
+                for block in row.iter_mut().skip(x).take(width) {
+                    if *block == Block::Void {
+                        *block = Block::Wall;
+                        walls += 1;
+                    }
+                }
+            }
+        }
+
+        layout
+    }
+
+
+    /// Fills the map with random noise, then smooths it into caves
+    /// A block becomes a wall if most of its neighbours are walls
+    fn caves(&self, seed: u64) -> Layout {
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut layout = Layout::boxed();
+
+        for row in layout.blocks.iter_mut().take(MAP_HEIGHT - 1).skip(1) {
+            for block in row.iter_mut().take(WORLD_WIDTH - 1).skip(1) {
+                if rng.gen_bool(self.density) {
+                    *block = Block::Wall;
+                }
+            }
+        }
+
+        for _ in 0..CAVE_SMOOTHING_ITERATIONS {
+
+            let previous = layout.blocks.clone();
+
+            for y in 1..MAP_HEIGHT - 1 {
+                for x in 1..WORLD_WIDTH - 1 {
+
+                    let mut wall_neighbours = 0;
+                    for row in previous.iter().skip(y - 1).take(3) {
+                        for block in row.iter().skip(x - 1).take(3) {
+                            if *block == Block::Wall {
+                                wall_neighbours += 1;
+                            }
+                        }
+                    }
+
+                    // The count includes the block itself
+                    layout.blocks[y][x] = if wall_neighbours >= CAVE_WALL_THRESHOLD {
+                        Block::Wall
+                    } else {
+                        Block::Void
+                    };
+                }
+            }
+        }
+
+        layout
+    }
+
+}
+
+
+/// A step of the curriculum, reached when the population's fitness crosses the threshold
+pub struct CurriculumStage {
+
+    /// The mean fitness of a generation needed to move past this stage
+    pub fitness_threshold: f64,
+    pub density: f64,
+    pub arena_margin: usize,
+
+}
+
+
+/// Makes the generated maps harder as the population improves
+pub struct Curriculum {
+
+    stage: usize,
+
+}
+
+
+impl Curriculum {
+
+    pub fn new() -> Self {
+        Curriculum {
+            stage: 0
+        }
+    }
+
+
+    pub fn stage(&self) -> usize {
+        self.stage
+    }
+
+
+    /// Moves to the next stage if the fitness crosses the threshold of the current one
+    /// Returns true if the stage changed
+    pub fn update(&mut self, mean_fitness: f64) -> bool {
+
+        let is_last = self.stage + 1 >= CURRICULUM.len();
+        if is_last || mean_fitness < CURRICULUM[self.stage].fitness_threshold {
+            return false;
+        }
+
+        self.stage += 1;
+        true
+    }
+
+
+    /// Sets the generator parameters of the current stage
+    pub fn apply(&self, generator: &mut Generator) {
+        let stage = &CURRICULUM[self.stage];
+        generator.density = stage.density;
+        generator.arena_margin = stage.arena_margin;
+    }
+
+}
@@ -0,0 +1,91 @@
+use rand::seq::SliceRandom;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::brain::{Brain, DenseBrain};
+use crate::consts::*;
+use crate::game_manager::{save_generation_file, GameManager};
+use crate::snake::Direction;
+
+
+/// What a snake saw, encoded as the input of a brain, and the direction it chose
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Demonstration {
+
+    pub input: Vec<f64>,
+    pub direction: Direction,
+
+}
+
+
+/// Plays headless for the given number of steps with all the snakes driven by the pathfinding bot,
+/// and saves what they saw and did as a dataset
+pub fn record(mut game_manager: GameManager, path: &str, steps: usize) {
+
+    game_manager.set_quiet(true);
+    game_manager.start_recording();
+
+    for _ in 0..steps {
+        game_manager.step();
+    }
+
+    let demonstrations = game_manager.take_demonstrations();
+
+    let json = serde_json::to_string(&demonstrations).expect(
+        "Failed to serialize the demonstrations"
+    );
+
+    std::fs::write(path, json).unwrap_or_else(
+        |_| panic!("Failed to write the demonstrations to {:?}", path)
+    );
+
+    println!("{} demonstrations recorded in {} steps saved to file {:?}", demonstrations.len(), steps, path);
+}
+
+
+/// Trains the first dense brain of the current generation to imitate the demonstrations of the dataset,
+/// by gradient descent on the softmax cross-entropy loss
+/// Saves a generation made of copies of the trained brain, to seed an evolutionary population
+pub fn train(game_manager: GameManager, path: &str, epochs: usize) {
+
+    let json = std::fs::read_to_string(path).unwrap_or_else(
+        |_| panic!("Failed to read the demonstrations file: {:?}", path)
+    );
+
+    let mut demonstrations: Vec<Demonstration> = serde_json::from_str(&json).unwrap_or_else(
+        |_| panic!("Failed to deserialize the demonstrations file: {:?}", path)
+    );
+
+    if demonstrations.iter().any(|demonstration| demonstration.input.len() != SIGHT_INPUT_SIZE) {
+        panic!("The demonstrations were recorded with a different sight size");
+    }
+
+    let mut brain: DenseBrain = game_manager.first_dense_brain("Imitation learning");
+
+    let mut rng = rand::thread_rng();
+
+    for epoch in 1..=epochs {
+
+        demonstrations.shuffle(&mut rng);
+
+        let mut total_loss = 0.0;
+        for demonstration in demonstrations.iter() {
+            total_loss += brain.train(&demonstration.input, demonstration.direction, IMITATION_LEARNING_RATE);
+        }
+
+        let correct = demonstrations.iter()
+            .filter(|demonstration| brain.choose(&demonstration.input) == demonstration.direction)
+            .count();
+
+        println!(
+            "Epoch {}: loss {:.4}, accuracy {:.1}%",
+            epoch,
+            total_loss / demonstrations.len().max(1) as f64,
+            correct as f64 * 100.0 / demonstrations.len().max(1) as f64
+        );
+    }
+
+    let brain = Brain::Dense(brain);
+    let brains: Vec<&Brain> = std::iter::repeat_n(&brain, GENERATION_SIZE).collect();
+
+    save_generation_file(&brains, 1);
+}
@@ -0,0 +1,135 @@
+use crate::brain::Brain;
+use crate::consts::*;
+use crate::game_manager::GameManager;
+
+
+/// Which islands send their best brains to which
+#[derive(Clone, Copy)]
+pub enum Topology {
+
+    /// Each island sends its brains to the next one, the last one to the first
+    Ring,
+    /// Each island sends its brains to all the others
+    Full,
+
+}
+
+
+impl Topology {
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "ring" => Topology::Ring,
+            "full" => Topology::Full,
+            _ => panic!("Unknown topology: {:?}. Expected \"ring\" or \"full\"", name),
+        }
+    }
+
+
+    /// The islands the given island receives brains from
+    fn sources(&self, island: usize, island_count: usize) -> Vec<usize> {
+        match self {
+            Topology::Ring => vec![(island + island_count - 1) % island_count],
+            Topology::Full => (0..island_count).filter(|source| *source != island).collect(),
+        }
+    }
+
+}
+
+
+/// How the best brains move between islands
+#[derive(Clone, Copy)]
+pub struct Migration {
+
+    pub topology: Topology,
+    /// The number of generations between migrations
+    pub interval: usize,
+    /// The number of brains each island sends to each of its neighbours
+    pub size: usize,
+
+}
+
+
+/// Evolves several independent populations headless, each on its own map,
+/// and periodically migrates the best brains between them
+/// Saves the generation of the island with the best mean fitness at the end
+pub fn run(mut islands: Vec<GameManager>, migration: Migration, generations: usize) {
+
+    for island in islands.iter_mut() {
+        island.set_quiet(true);
+    }
+
+    for generation in 1..=generations {
+
+        for (i, island) in islands.iter_mut().enumerate() {
+
+            let steps = run_generation(island);
+
+            if let Some(stats) = island.last_stats() {
+                println!(
+                    "Island {}: generation {}, steps {}, best fitness {:.1}, mean fitness {:.2}, species {}",
+                    i + 1,
+                    stats.generation,
+                    steps,
+                    stats.best_fitness,
+                    stats.mean_fitness,
+                    stats.species
+                );
+            }
+        }
+
+        if migration.interval > 0 && generation % migration.interval == 0 && generation < generations {
+            migrate(&mut islands, migration);
+            println!("Migrated up to {} brains between neighbouring islands", migration.size);
+        }
+    }
+
+    let best = islands.iter()
+        .max_by(|a, b| mean_fitness(a).total_cmp(&mean_fitness(b)))
+        .expect("No islands to run");
+
+    best.save_generation();
+}
+
+
+/// Runs the current generation of an island until all its snakes die, or for at most `MAX_GENERATION_STEPS`
+/// Returns the number of steps simulated
+fn run_generation(island: &mut GameManager) -> usize {
+
+    let generation = island.generation_count();
+
+    for steps in 1..=MAX_GENERATION_STEPS {
+        island.step();
+        if island.generation_count() != generation {
+            return steps;
+        }
+    }
+
+    island.next_generation();
+
+    MAX_GENERATION_STEPS
+}
+
+
+/// Sends the best brains of each island to its neighbours
+/// All the emigrants are chosen before any island receives immigrants
+fn migrate(islands: &mut [GameManager], migration: Migration) {
+
+    let emigrants: Vec<Vec<Brain>> = islands.iter()
+        .map(|island| island.elite_brains().iter().take(migration.size).cloned().collect())
+        .collect();
+
+    for (i, island) in islands.iter_mut().enumerate() {
+
+        let immigrants: Vec<Brain> = migration.topology.sources(i, emigrants.len()).into_iter()
+            .flat_map(|source| emigrants[source].iter().cloned())
+            .collect();
+
+        island.immigrate(&immigrants);
+    }
+}
+
+
+fn mean_fitness(island: &GameManager) -> f64 {
+    island.last_stats().map_or(f64::NEG_INFINITY, |stats| stats.mean_fitness)
+}
@@ -0,0 +1,268 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::map::{Block, Location};
+use crate::consts::*;
+
+
+/// Describes the static structure of a map: its walls, where snakes spawn and where apples can appear
+/// Layouts always have the size of the world
+#[derive(Clone)]
+pub struct Layout {
+
+    pub blocks: Vec<Vec<Block>>,
+    /// Preferred spawn locations of the snakes' heads, in spawn order
+    pub spawn_points: Vec<Location>,
+    /// Locations where apples can spawn. If empty, apples can spawn anywhere
+    pub apple_zones: Vec<Location>,
+
+}
+
+
+impl Layout {
+
+    /// An empty layout surrounded by walls
+    pub fn boxed() -> Self {
+
+        let mut blocks = vec![vec![Block::Void; WORLD_WIDTH]; MAP_HEIGHT];
+
+        for (y, row) in blocks.iter_mut().enumerate() {
+            for (x, block) in row.iter_mut().enumerate() {
+                if x == 0 || y == 0 || x == WORLD_WIDTH - 1 || y == MAP_HEIGHT - 1 {
+                    *block = Block::Wall;
+                }
+            }
+        }
+
+        Layout {
+            blocks,
+            spawn_points: Vec::new(),
+            apple_zones: Vec::new(),
+        }
+    }
+
+
+    /// Returns the built-in layout with the given name, if any
+    pub fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Layout::boxed()),
+            "pillars" => Some(Layout::pillars()),
+            "corridors" => Some(Layout::corridors()),
+            "maze" => Some(Layout::maze(BUILT_IN_MAZE_SEED, 0.0)),
+            _ => None,
+        }
+    }
+
+
+    /// Loads a built-in layout by name, or a layout file otherwise
+    pub fn load(name_or_path: &str) -> Self {
+
+        if let Some(layout) = Layout::built_in(name_or_path) {
+            return layout;
+        }
+
+        let text = std::fs::read_to_string(name_or_path).unwrap_or_else(
+            |_| panic!("Failed to read the layout file: {:?}", name_or_path)
+        );
+
+        Layout::parse(&text).unwrap_or_else(
+            |error| panic!("Invalid layout file {:?}: {}", name_or_path, error)
+        )
+    }
+
+
+    /// Parses an ASCII art layout
+    ///
+    /// - `#` is a wall
+    /// - `.` or a space is an empty block
+    /// - `S` is a spawn point
+    /// - `a` is an empty block where apples can spawn
+    ///
+    /// Layouts smaller than the world are padded with walls
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut layout = Layout {
+            blocks: vec![vec![Block::Wall; WORLD_WIDTH]; MAP_HEIGHT],
+            spawn_points: Vec::new(),
+            apple_zones: Vec::new(),
+        };
+
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.len() > MAP_HEIGHT {
+            return Err(format!("too many rows ({}), the maximum is {}", lines.len(), MAP_HEIGHT));
+        }
+
+        for (y, line) in lines.iter().enumerate() {
+
+            if line.chars().count() > WORLD_WIDTH {
+                return Err(format!("row {} is too long, the maximum width is {}", y + 1, WORLD_WIDTH));
+            }
+
+            for (x, symbol) in line.chars().enumerate() {
+
+                let location = Location::new(x, y);
+
+                layout.blocks[y][x] = match symbol {
+                    '#' => Block::Wall,
+                    '.' | ' ' => Block::Void,
+                    'S' => {
+                        layout.spawn_points.push(location);
+                        Block::Void
+                    },
+                    'a' => {
+                        layout.apple_zones.push(location);
+                        Block::Void
+                    },
+                    _ => return Err(format!("unknown symbol {:?} at row {}, column {}", symbol, y + 1, x + 1)),
+                };
+            }
+        }
+
+        Ok(layout)
+    }
+
+
+    fn set_wall(&mut self, x: usize, y: usize) {
+        if x < WORLD_WIDTH && y < MAP_HEIGHT {
+            self.blocks[y][x] = Block::Wall;
+        }
+    }
+
+
+    /// Square pillars laid out on a regular grid
+    pub fn pillars() -> Self {
+
+        let mut layout = Layout::boxed();
+
+        for y in (PILLAR_SPACING..MAP_HEIGHT - PILLAR_SIZE).step_by(PILLAR_SPACING) {
+            for x in (PILLAR_SPACING..WORLD_WIDTH - PILLAR_SIZE).step_by(PILLAR_SPACING) {
+                for dy in 0..PILLAR_SIZE {
+                    for dx in 0..PILLAR_SIZE {
+                        layout.set_wall(x + dx, y + dy);
+                    }
+                }
+            }
+        }
+
+        layout
+    }
+
+
+    /// Horizontal walls with an opening on alternating sides, forming a winding path
+    pub fn corridors() -> Self {
+
+        let mut layout = Layout::boxed();
+
+        for (i, y) in (CORRIDOR_WIDTH..MAP_HEIGHT - 1).step_by(CORRIDOR_WIDTH + 1).enumerate() {
+
+            let (start, end) = if i % 2 == 0 {
+                (1, WORLD_WIDTH - 1 - CORRIDOR_OPENING)
+            } else {
+                (1 + CORRIDOR_OPENING, WORLD_WIDTH - 1)
+            };
+
+            for x in start..end {
+                layout.set_wall(x, y);
+            }
+        }
+
+        layout
+    }
+
+
+    /// A maze generated with a randomized depth-first search
+    /// Passages are `MAZE_PASSAGE_WIDTH` blocks wide so that snakes can spawn and turn in them
+    /// Each wall between two cells is then removed with the given chance, creating loops
+    /// A chance of 0 generates a perfect maze
+    pub fn maze(seed: u64, loop_chance: f64) -> Self {
+
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let pitch = MAZE_PASSAGE_WIDTH + 1;
+        let columns = (WORLD_WIDTH - 1) / pitch;
+        let rows = (MAP_HEIGHT - 1) / pitch;
+
+        // Start with everything walled in and carve the passages
+        let mut layout = Layout {
+            blocks: vec![vec![Block::Wall; WORLD_WIDTH]; MAP_HEIGHT],
+            spawn_points: Vec::new(),
+            apple_zones: Vec::new(),
+        };
+
+        let carve = |layout: &mut Layout, x: usize, y: usize, width: usize, height: usize| {
+            for row in layout.blocks.iter_mut().skip(y).take(height) {
+                for block in row.iter_mut().skip(x).take(width) {
+                    *block = Block::Void;
+                }
+            }
+        };
+
+        // Carve the passage between two adjacent cells, including the wall separating them
+        let connect = |layout: &mut Layout, (column, row): (usize, usize), (next_column, next_row): (usize, usize)| {
+            let x = 1 + column.min(next_column) * pitch;
+            let y = 1 + row.min(next_row) * pitch;
+            let (width, height) = if next_row == row {
+                (pitch + MAZE_PASSAGE_WIDTH, MAZE_PASSAGE_WIDTH)
+            } else {
+                (MAZE_PASSAGE_WIDTH, pitch + MAZE_PASSAGE_WIDTH)
+            };
+            carve(layout, x, y, width, height);
+        };
+
+        let mut visited = vec![vec![false; columns]; rows];
+        let mut stack = vec![(0, 0)];
+        visited[0][0] = true;
+        carve(&mut layout, 1, 1, MAZE_PASSAGE_WIDTH, MAZE_PASSAGE_WIDTH);
+
+        while let Some(&(column, row)) = stack.last() {
+
+            let mut neighbours = Vec::with_capacity(4);
+            if column > 0 && !visited[row][column - 1] { neighbours.push((column - 1, row)); }
+            if row > 0 && !visited[row - 1][column] { neighbours.push((column, row - 1)); }
+            if column + 1 < columns && !visited[row][column + 1] { neighbours.push((column + 1, row)); }
+            if row + 1 < rows && !visited[row + 1][column] { neighbours.push((column, row + 1)); }
+
+            if neighbours.is_empty() {
+                stack.pop();
+                continue;
+            }
+
+            let (next_column, next_row) = neighbours[rng.gen_range(0..neighbours.len())];
+            visited[next_row][next_column] = true;
+
+            connect(&mut layout, (column, row), (next_column, next_row));
+
+            stack.push((next_column, next_row));
+        }
+
+        // Open additional passages to create loops
+        for row in 0..rows {
+            for column in 0..columns {
+                if column + 1 < columns && rng.gen_bool(loop_chance) {
+                    connect(&mut layout, (column, row), (column + 1, row));
+                }
+                if row + 1 < rows && rng.gen_bool(loop_chance) {
+                    connect(&mut layout, (column, row), (column, row + 1));
+                }
+            }
+        }
+
+        layout
+    }
+
+
+    /// Fills the given number of blocks from each edge with walls, shrinking the playable area
+    pub fn shrink(&mut self, margin: usize) {
+        for (y, row) in self.blocks.iter_mut().enumerate() {
+            for (x, block) in row.iter_mut().enumerate() {
+                if x < margin || y < margin || x >= WORLD_WIDTH - margin || y >= MAP_HEIGHT - margin {
+                    *block = Block::Wall;
+                }
+            }
+        }
+
+        self.spawn_points.retain(|point| matches!(self.blocks[point.y][point.x], Block::Void));
+        self.apple_zones.retain(|zone| matches!(self.blocks[zone.y][zone.x], Block::Void));
+    }
+
+}
@@ -4,71 +4,115 @@ mod snake;
 mod game_manager;
 mod render;
 mod brain;
+mod cli;
+mod window;
+mod tui;
+mod export;
+mod layout;
+mod generator;
+mod resolution;
+mod neat;
+mod species;
+mod novelty;
+mod islands;
+mod mutation;
+mod strategy;
+mod pathfinding;
+mod imitation;
+mod reinforcement;
+mod controller;
+mod benchmark;
+mod policy;
+
+
+use cli::{Export, Options, Renderer};
+use layout::Layout;
 
 
-use opengl_graphics::{GlGraphics, OpenGL};
-use piston::{ButtonEvent, EventLoop};
-use piston::event_loop::{EventSettings, Events};
-use piston::input::{RenderEvent, UpdateEvent};
-use piston::window::WindowSettings;
+fn main() {
 
-use consts::*;
-use piston_window::PistonWindow;
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let options = Options::parse(&args);
 
 
-fn main() {
+    if let Some(passes) = options.throughput_passes {
+        benchmark::throughput(passes);
+        return;
+    }
 
-    let args = std::env::args().collect::<Vec<String>>();
-    
 
-    // Initialize graphics
+    // Evolve several populations headless if requested
 
-    let opengl = OpenGL::V3_2;
-    let mut window: PistonWindow = WindowSettings::new(WIN_TITLE, [WIN_WIDTH, WIN_HEIGHT])
-        .exit_on_esc(true)
-        .build()
-        .expect("Could not create window");
-    let mut gl = GlGraphics::new(opengl);
+    if options.islands > 0 {
+        let islands = (0..options.islands).map(|_| create_game_manager(&options)).collect();
+        islands::run(islands, options.migration, options.generations);
+        return;
+    }
 
-    let event_settings = EventSettings::new()
-    // Lazy mode is disabled to allow updates to be independent of user input
-        .lazy(false);
-    let mut events = Events::new(event_settings);
+    let game_manager = create_game_manager(&options);
 
+    if let Some(path) = &options.record_demonstrations {
+        imitation::record(game_manager, path, options.steps);
+        return;
+    }
 
-    // Initialize game manager
+    if let Some(path) = &options.imitation_dataset {
+        imitation::train(game_manager, path, options.epochs);
+        return;
+    }
 
-    let mut game_manager = game_manager::GameManager::new(&mut window);
+    if options.evolution_strategy {
+        strategy::run(game_manager, options.generations);
+        return;
+    }
 
-    match args.len() {
-        1 => game_manager.initialize(),
-        2 => {
-            let gen_path = &args[1];
-            game_manager.initialize_from_file(gen_path);
-        },
-        _ => panic!("Invalid number of arguments"),
+    if let Some(episodes) = options.benchmark_episodes {
+        benchmark::run(game_manager, episodes);
+        return;
     }
 
+    if options.reinforcement {
+        reinforcement::train(game_manager, options.generations);
+        return;
+    }
 
-    // Game loop
 
-    while let Some(event) = events.next(&mut window) {
+    // Export headless if requested, otherwise run the game with the chosen frontend
 
-        // Rendering
-        if let Some(args) = event.render_args() {
-            game_manager.draw(&args, &mut gl, &mut window, &event);
-        }
+    match (&options.export, &options.renderer) {
+        (Some(Export::Png(path)), _) => export::export_png(game_manager, path, options.steps),
+        (Some(Export::Gif(path)), _) => export::record_gif(game_manager, path, options.steps),
+        (None, Renderer::Window) => window::run(game_manager),
+        (None, Renderer::Terminal) => tui::run(game_manager),
+    }
 
-        // Updating
-        if let Some(args) = event.update_args() {
-            game_manager.update(&args);
-        }
+}
+
+
+/// Creates and initializes a game manager with the given options
+fn create_game_manager(options: &Options) -> game_manager::GameManager {
+
+    let mut game_manager = game_manager::GameManager::new(Layout::load(&options.layout));
 
-        // Input
-        if let Some(args) = event.button_args() {
-            game_manager.handle_input(&args);
-        }
+    game_manager.set_wrap(options.wrap);
+    game_manager.set_item_weights(options.item_weights);
+    game_manager.set_corpse_rules(options.corpse_rules);
+    game_manager.set_kill_reward(options.kill_reward);
+    game_manager.set_teams(options.teams);
+    game_manager.set_brain_kind(options.brain_kind);
+    game_manager.set_novelty_search(options.novelty_search.clone());
+    game_manager.set_mutation_config(options.mutation);
+    game_manager.set_bots(options.bots.clone());
+    game_manager.set_policy(if options.watch { options.policy.greedy() } else { options.policy });
+
+    if let Some(generator) = options.generator {
+        game_manager.set_generator(generator, options.curriculum);
     }
 
-}
+    match &options.generation_file {
+        None => game_manager.initialize(),
+        Some(gen_path) => game_manager.initialize_from_file(gen_path),
+    }
 
+    game_manager
+}
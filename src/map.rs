@@ -1,16 +1,20 @@
+use std::collections::VecDeque;
+
 use graphics::{types::Color, Context};
 use opengl_graphics::GlGraphics;
+use rand::Rng;
 use piston::RenderArgs;
 
-use crate::render::{render_block, Drawable, WindowCoordinates};
+use crate::render::{render_block, head_color, Camera, Drawable, WindowCoordinates};
 use crate::consts::*;
-use crate::snake::Direction;
+use crate::layout::Layout;
+use crate::snake::{Direction, SnakeId};
 
 
 pub type SubmapMatrix = [[Block; SIGHT_SIZE]; SIGHT_SIZE];
 
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct Location {
 
     pub x: usize,
@@ -30,37 +34,155 @@ impl Location {
 
 
     /// Returns the new location after moving in the given direction
-    pub fn trans(&self, amount: i64, direction: Direction) -> Self {
-        match direction {
-            Direction::Up => Location::new(self.x, (self.y as i64 - amount) as usize),
-            Direction::Down => Location::new(self.x, (self.y as i64 + amount) as usize),
-            Direction::Left => Location::new((self.x as i64 - amount) as usize, self.y),
-            Direction::Right => Location::new((self.x as i64 + amount) as usize, self.y),
+    /// Returns None if the new location is outside the world
+    pub fn trans(&self, amount: i64, direction: Direction) -> Option<Self> {
+        let (dx, dy) = direction.offset(amount);
+
+        let x = self.x as i64 + dx;
+        let y = self.y as i64 + dy;
+
+        if x < 0 || y < 0 || x >= WORLD_WIDTH as i64 || y >= MAP_HEIGHT as i64 {
+            return None;
         }
+
+        Some(Location::new(x as usize, y as usize))
+    }
+
+
+    /// Returns the new location after moving in the given direction
+    /// Moving off an edge of the world re-enters on the opposite edge
+    pub fn wrapping_trans(&self, amount: i64, direction: Direction) -> Self {
+        let (dx, dy) = direction.offset(amount);
+
+        Location::new(
+            (self.x as i64 + dx).rem_euclid(WORLD_WIDTH as i64) as usize,
+            (self.y as i64 + dy).rem_euclid(MAP_HEIGHT as i64) as usize
+        )
     }
 
 }
 
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Block {
     Void,
     Wall,
-    SnakeTail,
-    SnakeHead,
+    SnakeTail(SnakeId),
+    SnakeHead(SnakeId),
+    Item(Item),
+}
+
+
+/// Items snakes can eat
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Item {
+
+    /// Makes the snake grow by one bit
     Apple,
+    /// Makes the snake grow by several bits
+    GoldenApple,
+    /// Makes the snake shrink, killing it if it becomes too short
+    Poison,
+    /// Makes the snake shrink, but never below the minimum length
+    ShrinkPill,
+
+}
+
+
+/// Determines what happens to the body of dead snakes
+#[derive(Clone, Copy)]
+pub struct CorpseRules {
+
+    /// The chance of each block of the body turning into an apple
+    pub ratio: f64,
+    /// The number of steps after which uneaten corpse apples disappear. 0 means never
+    pub decay_steps: usize,
+
+}
+
+
+/// Relative spawn chance of each item, in the order of `Item::ALL`
+pub type ItemWeights = [f64; 4];
+
+
+impl Item {
+
+    pub const ALL: [Item; 4] = [Item::Apple, Item::GoldenApple, Item::Poison, Item::ShrinkPill];
+
+
+    pub fn color(&self) -> Color {
+        match self {
+            Item::Apple => APPLE_COLOR,
+            Item::GoldenApple => GOLDEN_APPLE_COLOR,
+            Item::Poison => POISON_COLOR,
+            Item::ShrinkPill => SHRINK_PILL_COLOR,
+        }
+    }
+
+
+    /// The number of bits the snake gains by eating the item
+    /// Negative values make the snake shrink
+    pub fn growth(&self) -> i64 {
+        match self {
+            Item::Apple => 1,
+            Item::GoldenApple => GOLDEN_APPLE_GROWTH,
+            Item::Poison => -POISON_DAMAGE,
+            Item::ShrinkPill => -SHRINK_PILL_AMOUNT,
+        }
+    }
+
+
+    /// Whether eating the item kills the snake if it would become shorter than the minimum length
+    pub fn is_lethal(&self) -> bool {
+        matches!(self, Item::Poison)
+    }
+
+
+    /// Whether eating the item satisfies the snake's hunger
+    pub fn is_food(&self) -> bool {
+        !matches!(self, Item::Poison)
+    }
+
+
+    /// Chooses a random item with the given relative spawn chances
+    pub fn random(weights: &ItemWeights) -> Self {
+
+        let total: f64 = weights.iter().sum();
+        let mut choice = rand::thread_rng().gen_range(0.0..total);
+
+        for (item, weight) in Item::ALL.iter().zip(weights.iter()) {
+            if choice < *weight {
+                return *item;
+            }
+            choice -= weight;
+        }
+
+        Item::Apple
+    }
+
 }
 
 
 impl Block {
 
+    /// The default color of the block, regardless of which snake owns it
     pub fn color(&self) -> Color {
         match self {
             Block::Void => VOID_COLOR,
             Block::Wall => WALL_COLOR,
-            Block::SnakeTail => TAIL_COLOR,
-            Block::Apple => APPLE_COLOR,
-            Block::SnakeHead => HEAD_COLOR,
+            Block::SnakeTail(_) => TAIL_COLOR,
+            Block::Item(item) => item.color(),
+            Block::SnakeHead(_) => HEAD_COLOR,
+        }
+    }
+
+
+    /// Returns the snake that occupies the block, if any
+    pub fn owner(&self) -> Option<SnakeId> {
+        match self {
+            Block::SnakeTail(id) |
+            Block::SnakeHead(id) => Some(*id),
+            _ => None,
         }
     }
 
@@ -69,84 +191,287 @@ impl Block {
 
 pub struct Map {
 
-    pub blocks: Vec<Vec<Block>>
+    pub blocks: Vec<Vec<Block>>,
+    /// The body color of each snake spawned on the map, indexed by snake id
+    snake_colors: Vec<Color>,
+    /// The team of each snake spawned on the map, indexed by snake id
+    /// Snakes that aren't part of a team are alone in the team with their own id
+    snake_teams: Vec<usize>,
+    /// Locations where items can spawn. If empty, items can spawn anywhere
+    apple_zones: Vec<Location>,
+    /// Whether the map is toroidal, with opposite edges connected
+    wrap: bool,
+    /// If set, dead snakes leave apples behind
+    corpse_rules: Option<CorpseRules>,
+    /// Corpse apples in the order they decay, with the step they decay at
+    decaying: VecDeque<(Location, usize)>,
+    /// The step the latest corpse apple at each location decays at
+    decay_steps: Vec<Vec<Option<usize>>>,
+    /// The number of steps since the map was created
+    step_count: usize,
 
 }
 
 
 impl Map {
 
-    // Spawn an apple in a random valid location
-    pub fn spawn_apple(&mut self) {
+    // Spawn an item in a random valid location
+    // Only the apple zones are considered, if the layout defines any
+    // Gives up after a number of attempts if no free location is found
+    pub fn spawn_item(&mut self, item: Item) {
 
-        let mut new_location = Location::new(0, 0);
+        let mut rng = rand::thread_rng();
 
-        loop {
+        for _ in 0..MAX_APPLE_SPAWN_ATTEMPTS {
 
-            new_location.x = rand::random::<usize>() % WORLD_WIDTH;
-            new_location.y = rand::random::<usize>() % MAP_HEIGHT;
+            let new_location = if self.apple_zones.is_empty() {
+                Location::new(
+                    rng.gen_range(0..WORLD_WIDTH),
+                    rng.gen_range(0..MAP_HEIGHT)
+                )
+            } else {
+                self.apple_zones[rng.gen_range(0..self.apple_zones.len())]
+            };
 
             if self.get(new_location) == Block::Void {
-                break;
+                self.set_item_block(new_location, item);
+                return;
             }
         }
 
-        self.set_apple_block(new_location);
-
     }
 
 
     /// Create a new empty map, used as a placeholder
     pub fn empty_new() -> Self {
         Self {
-            blocks: Vec::new()
+            blocks: Vec::new(),
+            snake_colors: Vec::new(),
+            snake_teams: Vec::new(),
+            apple_zones: Vec::new(),
+            wrap: false,
+            corpse_rules: None,
+            decaying: VecDeque::new(),
+            decay_steps: Vec::new(),
+            step_count: 0,
         }
     }
 
 
-    /// Create a new complete map with the walls
-    pub fn create_new() -> Self {
+    /// Create a new map with the walls of the given layout
+    /// If the map wraps around, the walls on its edges are removed
+    pub fn from_layout(layout: &Layout, wrap: bool) -> Self {
 
-        let mut blocks = Vec::with_capacity(MAP_HEIGHT as usize);
-        
-        // Construct the walls when creating the map
+        let mut blocks = layout.blocks.clone();
 
-        blocks.push(vec![Block::Wall; WORLD_WIDTH as usize]);
+        if wrap {
+            for (y, row) in blocks.iter_mut().enumerate() {
+                for (x, block) in row.iter_mut().enumerate() {
+                    if x == 0 || y == 0 || x == WORLD_WIDTH - 1 || y == MAP_HEIGHT - 1 {
+                        *block = Block::Void;
+                    }
+                }
+            }
+        }
 
-        for _ in 1..MAP_HEIGHT-1 {
-            let mut row = Vec::with_capacity(WORLD_WIDTH as usize);
-            row.push(Block::Wall);
-            for _ in 1..WORLD_WIDTH-1 {
-                row.push(Block::Void);
+        Map {
+            blocks,
+            snake_colors: Vec::new(),
+            snake_teams: Vec::new(),
+            apple_zones: layout.apple_zones.clone(),
+            wrap,
+            corpse_rules: None,
+            decaying: VecDeque::new(),
+            decay_steps: vec![vec![None; WORLD_WIDTH]; MAP_HEIGHT],
+            step_count: 0,
+        }
+    }
+
+
+    /// Makes dead snakes leave apples behind according to the given rules
+    pub fn set_corpse_rules(&mut self, rules: Option<CorpseRules>) {
+        self.corpse_rules = rules;
+    }
+
+
+    /// Advances the map by one step, removing the decayed corpse apples
+    pub fn tick(&mut self) {
+
+        self.step_count += 1;
+
+        while let Some(&(location, decay_step)) = self.decaying.front() {
+
+            if decay_step > self.step_count {
+                break;
+            }
+            self.decaying.pop_front();
+
+            // The location may have been reused by a newer corpse
+            if self.decay_steps[location.y][location.x] != Some(decay_step) {
+                continue;
+            }
+            self.decay_steps[location.y][location.x] = None;
+
+            if self.get(location) == Block::Item(Item::Apple) {
+                self.free_block(location);
             }
-            row.push(Block::Wall);
-            blocks.push(row);
         }
+    }
 
-        blocks.push(vec![Block::Wall; WORLD_WIDTH as usize]);
 
-        Map {
-            blocks
+    /// Frees the blocks occupied by the body of a dead snake
+    /// Some of the blocks turn into apples if corpse rules are set
+    pub fn remove_body(&mut self, bits: &[Location]) {
+
+        let rules = match self.corpse_rules {
+            Some(rules) => rules,
+            None => {
+                for bit in bits {
+                    self.free_block(*bit);
+                }
+                return;
+            }
+        };
+
+        let mut rng = rand::thread_rng();
+
+        for bit in bits {
+
+            if !rng.gen_bool(rules.ratio) {
+                self.free_block(*bit);
+                continue;
+            }
+
+            self.set_item_block(*bit, Item::Apple);
+
+            if rules.decay_steps > 0 {
+                let decay_step = self.step_count + rules.decay_steps;
+                self.decay_steps[bit.y][bit.x] = Some(decay_step);
+                self.decaying.push_back((*bit, decay_step));
+            }
+        }
+    }
+
+
+    /// Returns the new location after moving in the given direction
+    /// Wraps around the edges if the map is toroidal
+    /// Returns None if the new location is outside a non-toroidal map
+    pub fn trans(&self, location: Location, amount: i64, direction: Direction) -> Option<Location> {
+        if self.wrap {
+            Some(location.wrapping_trans(amount, direction))
+        } else {
+            location.trans(amount, direction)
         }
+    }
 
+
+    /// Returns whether the location is inside the map
+    pub fn contains(&self, location: Location) -> bool {
+        location.x < WORLD_WIDTH && location.y < MAP_HEIGHT
+    }
+
+
+    /// Returns the directions a snake can face if spawned with the head at the given location
+    /// The whole body and the block in front of the head must be free
+    pub fn spawn_directions(&self, head: Location) -> Vec<Direction> {
+        Direction::ALL.iter()
+            .copied()
+            .filter(|direction| {
+                (-1..INITIAL_SNAKE_LENGTH as i64).all(|i| {
+                    self.trans(head, i, direction.opposite())
+                        .is_some_and(|location| self.get(location) == Block::Void)
+                })
+            })
+            .collect()
+    }
+
+
+    /// Finds the closest location to the preferred one where a snake can spawn
+    /// Searches in growing squares around the preferred location
+    pub fn find_spawn_location(&self, preferred: Location) -> Option<Location> {
+
+        let max_radius = WORLD_WIDTH.max(MAP_HEIGHT) as i64;
+
+        for radius in 0..max_radius {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+
+                    // Only check the border of the square, the inside was already checked
+                    if dx.abs() != radius && dy.abs() != radius {
+                        continue;
+                    }
+
+                    let x = preferred.x as i64 + dx;
+                    let y = preferred.y as i64 + dy;
+                    if x < 0 || y < 0 {
+                        continue;
+                    }
+
+                    let location = Location::new(x as usize, y as usize);
+                    if self.contains(location) && !self.spawn_directions(location).is_empty() {
+                        return Some(location);
+                    }
+                }
+            }
+        }
+
+        None
     }
 
 
     /// Returns the block at the given location
+    /// Locations outside the map are treated as walls
     pub fn get(&self, location: Location) -> Block {
+        if !self.contains(location) {
+            return Block::Wall;
+        }
         self.blocks[location.y][location.x]
     }
 
 
-    /// Sets the given location as blocked by the snake head
-    pub fn set_head_block(&mut self, location: Location) {
-        self.blocks[location.y][location.x] = Block::SnakeHead;
+    /// Registers a new snake with the given body color
+    /// Returns the id the snake is identified by on the map
+    pub fn add_snake(&mut self, color: Color) -> SnakeId {
+        self.snake_colors.push(color);
+        self.snake_teams.push(self.snake_teams.len());
+        self.snake_colors.len() - 1
+    }
+
+
+    /// Moves the given snake to a team, changing its body color to the team's
+    pub fn set_snake_team(&mut self, id: SnakeId, team: usize, color: Color) {
+        self.snake_teams[id] = team;
+        self.snake_colors[id] = color;
+    }
+
+
+    /// The team of each snake, indexed by snake id
+    pub fn snake_teams(&self) -> &[usize] {
+        &self.snake_teams
+    }
+
+
+    /// Returns the color the given location should be drawn with
+    /// Snake blocks are drawn with the color of the snake that owns them
+    pub fn color_at(&self, location: Location) -> Color {
+        let block = self.get(location);
+        match block {
+            Block::SnakeTail(id) => self.snake_colors[id],
+            Block::SnakeHead(id) => head_color(self.snake_colors[id]),
+            _ => block.color(),
+        }
+    }
+
+
+    /// Sets the given location as blocked by the head of the given snake
+    pub fn set_head_block(&mut self, location: Location, id: SnakeId) {
+        self.blocks[location.y][location.x] = Block::SnakeHead(id);
     }
 
 
-    /// Sets the given location as blocked by the snake tail
-    pub fn set_tail_block(&mut self, location: Location) {
-        self.blocks[location.y][location.x] = Block::SnakeTail;
+    /// Sets the given location as blocked by the tail of the given snake
+    pub fn set_tail_block(&mut self, location: Location, id: SnakeId) {
+        self.blocks[location.y][location.x] = Block::SnakeTail(id);
     }
 
 
@@ -156,13 +481,14 @@ impl Map {
     }
 
 
-    /// Sets the given location as occupied by the apple
-    pub fn set_apple_block(&mut self, location: Location) {
-        self.blocks[location.y][location.x] = Block::Apple;
+    /// Sets the given location as occupied by the item
+    pub fn set_item_block(&mut self, location: Location, item: Item) {
+        self.blocks[location.y][location.x] = Block::Item(item);
     }
 
 
     /// Returns the submap centered around the given location
+    /// On toroidal maps, the submap wraps around the edges, otherwise out of bounds blocks are seen as void
     pub fn get_submap(&self, center: Location) -> SubmapMatrix {
         // Calculate the top left corner of the submap
         let top_left_x = center.x as i64 - SIGHT_RADIUS as i64;
@@ -175,16 +501,20 @@ impl Map {
         for (y, submap_row) in submap.iter_mut().enumerate() {
 
             // Check if the coordinates are out of bounds
-            let y = top_left_y + y as i64;
-            if y < 0 || y >= MAP_HEIGHT as i64 {
+            let mut y = top_left_y + y as i64;
+            if self.wrap {
+                y = y.rem_euclid(MAP_HEIGHT as i64);
+            } else if y < 0 || y >= MAP_HEIGHT as i64 {
                 continue;
             }
 
             for (x, block) in submap_row.iter_mut().enumerate() {
 
                 // Check if the coordinates are out of bounds
-                let x = top_left_x + x as i64;
-                if x < 0 || x >= WORLD_WIDTH as i64 {
+                let mut x = top_left_x + x as i64;
+                if self.wrap {
+                    x = x.rem_euclid(WORLD_WIDTH as i64);
+                } else if x < 0 || x >= WORLD_WIDTH as i64 {
                     continue;
                 }
 
@@ -201,16 +531,19 @@ impl Map {
 
 impl Drawable for Map {
 
-    fn draw(&self, args: &RenderArgs, gl: &mut GlGraphics, _window: &mut piston_window::PistonWindow, _event: &piston::Event) {
+    fn draw(&self, args: &RenderArgs, gl: &mut GlGraphics, _window: &mut piston_window::PistonWindow, _event: &piston::Event, camera: &Camera) {
+
+        gl.draw(args.viewport(), |mut context: Context, gl: &mut GlGraphics| {
 
-        gl.draw(args.viewport(), |context: Context, gl: &mut GlGraphics| {
+            context.transform = camera.transform(context.transform);
 
             // Draw the world map block by block
             for (y, row) in self.blocks.iter().enumerate() {
-                for (x, block) in row.iter().enumerate() {
+                for x in 0..row.len() {
+                    let location = Location::new(x, y);
                     render_block(
-                        block.color(),
-                        WindowCoordinates::from_map_location(Location::new(x, y)),
+                        self.color_at(location),
+                        WindowCoordinates::from_map_location(location),
                         &context,
                         gl
                     )
@@ -223,3 +556,97 @@ impl Drawable for Map {
 
 }
 
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+
+    /// A layout without any wall, not even on its edges
+    fn open_layout() -> Layout {
+        Layout {
+            blocks: vec![vec![Block::Void; WORLD_WIDTH]; MAP_HEIGHT],
+            spawn_points: Vec::new(),
+            apple_zones: Vec::new(),
+        }
+    }
+
+
+    #[test]
+    fn trans_off_each_edge_is_none() {
+
+        let top_left = Location::new(0, 0);
+        let bottom_right = Location::new(WORLD_WIDTH - 1, MAP_HEIGHT - 1);
+
+        assert_eq!(top_left.trans(1, Direction::Up), None);
+        assert_eq!(top_left.trans(1, Direction::Left), None);
+        assert_eq!(bottom_right.trans(1, Direction::Down), None);
+        assert_eq!(bottom_right.trans(1, Direction::Right), None);
+
+        assert_eq!(top_left.trans(1, Direction::Down), Some(Location::new(0, 1)));
+        assert_eq!(bottom_right.trans(1, Direction::Left), Some(Location::new(WORLD_WIDTH - 2, MAP_HEIGHT - 1)));
+    }
+
+
+    #[test]
+    fn get_outside_is_wall() {
+
+        let map = Map::from_layout(&open_layout(), false);
+
+        assert_eq!(map.get(Location::new(0, 0)), Block::Void);
+        assert_eq!(map.get(Location::new(WORLD_WIDTH, 0)), Block::Wall);
+        assert_eq!(map.get(Location::new(0, MAP_HEIGHT)), Block::Wall);
+        assert_eq!(map.get(Location::new(WORLD_WIDTH, MAP_HEIGHT)), Block::Wall);
+    }
+
+
+    #[test]
+    fn spawn_directions_next_to_border_walls() {
+
+        let map = Map::from_layout(&Layout::boxed(), false);
+
+        // Facing the wall, or with the body in it, is not allowed
+        assert_eq!(map.spawn_directions(Location::new(1, MAP_HEIGHT / 2)), vec![Direction::Up, Direction::Down]);
+        assert!(map.spawn_directions(Location::new(1, 1)).is_empty());
+    }
+
+
+    #[test]
+    fn spawn_directions_on_the_edge_without_walls() {
+
+        let map = Map::from_layout(&open_layout(), false);
+
+        // The body and the block in front of the head must stay inside the map
+        assert_eq!(map.spawn_directions(Location::new(0, MAP_HEIGHT / 2)), vec![Direction::Up, Direction::Down]);
+        assert_eq!(map.spawn_directions(Location::new(WORLD_WIDTH / 2, MAP_HEIGHT - 1)), vec![Direction::Left, Direction::Right]);
+        assert!(map.spawn_directions(Location::new(0, 0)).is_empty());
+
+        // A wrapping map has no edge
+        let map = Map::from_layout(&open_layout(), true);
+        assert_eq!(map.spawn_directions(Location::new(0, 0)), Direction::ALL.to_vec());
+    }
+
+
+    #[test]
+    fn find_spawn_location_in_corners() {
+
+        for layout in [Layout::boxed(), open_layout()] {
+
+            let map = Map::from_layout(&layout, false);
+
+            for preferred in [Location::new(0, 0), Location::new(WORLD_WIDTH - 1, MAP_HEIGHT - 1)] {
+
+                let location = map.find_spawn_location(preferred).unwrap();
+
+                assert!(map.contains(location));
+                assert_eq!(map.get(location), Block::Void);
+                assert!(!map.spawn_directions(location).is_empty());
+                assert!(location.x.abs_diff(preferred.x) <= INITIAL_SNAKE_LENGTH);
+                assert!(location.y.abs_diff(preferred.y) <= INITIAL_SNAKE_LENGTH);
+            }
+        }
+    }
+
+}
@@ -0,0 +1,180 @@
+use rand::Rng;
+
+use crate::consts::*;
+
+
+/// How the parameters of a brain are changed when breeding offspring
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operator {
+
+    /// Adds uniform noise in the range [-step, step] to some parameters
+    Uniform,
+    /// Adds gaussian noise with a standard deviation of step to some parameters
+    Gaussian,
+    /// Replaces some parameters with new random values
+    Reset,
+    /// Scales all the parameters of a layer by the same random factor around 1
+    LayerScale,
+
+}
+
+
+impl Operator {
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "uniform" => Operator::Uniform,
+            "gaussian" => Operator::Gaussian,
+            "reset" => Operator::Reset,
+            "scale" => Operator::LayerScale,
+            _ => panic!("Unknown mutation operator: {:?}. Expected \"uniform\", \"gaussian\", \"reset\" or \"scale\"", name),
+        }
+    }
+
+}
+
+
+/// How the mutation chance and step change over the generations
+#[derive(Clone, Copy)]
+pub enum Schedule {
+
+    Constant,
+    /// Decreases linearly down to `MIN_MUTATION_FACTOR` over `MUTATION_ANNEALING_GENERATIONS` generations
+    Linear,
+    /// Decreases by `MUTATION_DECAY` every generation, down to `MIN_MUTATION_FACTOR`
+    Exponential,
+
+}
+
+
+impl Schedule {
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "constant" => Schedule::Constant,
+            "linear" => Schedule::Linear,
+            "exponential" => Schedule::Exponential,
+            _ => panic!("Unknown mutation schedule: {:?}. Expected \"constant\", \"linear\" or \"exponential\"", name),
+        }
+    }
+
+
+    /// The factor the mutation chance and step are multiplied by at the given generation
+    fn factor(&self, generation: usize) -> f64 {
+
+        let elapsed = generation.saturating_sub(1) as f64;
+
+        let factor = match self {
+            Schedule::Constant => 1.0,
+            Schedule::Linear => 1.0 - elapsed / MUTATION_ANNEALING_GENERATIONS as f64,
+            Schedule::Exponential => MUTATION_DECAY.powf(elapsed),
+        };
+
+        factor.max(MIN_MUTATION_FACTOR)
+    }
+
+}
+
+
+/// The mutation settings chosen for the whole run
+#[derive(Clone, Copy)]
+pub struct MutationConfig {
+
+    pub operator: Operator,
+    pub schedule: Schedule,
+    /// Whether each brain carries its own mutation step sizes, which are mutated along with it
+    pub self_adaptive: bool,
+
+}
+
+
+impl MutationConfig {
+
+    pub fn new() -> Self {
+        MutationConfig {
+            operator: Operator::Uniform,
+            schedule: Schedule::Constant,
+            self_adaptive: false,
+        }
+    }
+
+
+    /// The mutation applied to the offspring of the given generation
+    pub fn at(&self, generation: usize) -> Mutation {
+
+        let factor = self.schedule.factor(generation);
+
+        Mutation {
+            operator: self.operator,
+            chance: MUTATION_CHANCE * factor,
+            step: MAX_MUTATION * factor,
+            self_adaptive: self.self_adaptive,
+        }
+    }
+
+}
+
+
+/// The mutation applied to the offspring of a generation
+#[derive(Clone, Copy)]
+pub struct Mutation {
+
+    pub operator: Operator,
+    /// The chance of each parameter being mutated, or of each layer being scaled
+    pub chance: f64,
+    pub step: f64,
+    pub self_adaptive: bool,
+
+}
+
+
+impl Mutation {
+
+    /// Mutates a group of parameters, such as the weights and biases of a layer
+    /// In the self-adaptive mode, the group's own step size is mutated first, then used in place of the step.
+    /// Groups without a step size yet start from the current step
+    pub fn apply<'a>(&self, parameters: impl Iterator<Item = &'a mut f64>, step_size: &mut Option<f64>) {
+
+        let mut rng = rand::thread_rng();
+
+        let step = if self.self_adaptive {
+            let step = step_size.unwrap_or(self.step) * (SELF_ADAPTATION_RATE * gaussian(&mut rng)).exp();
+            let step = step.clamp(MIN_STEP_SIZE, MAX_STEP_SIZE);
+            *step_size = Some(step);
+            step
+        } else {
+            self.step
+        };
+
+        if self.operator == Operator::LayerScale {
+            if rng.gen_bool(self.chance) {
+                let factor = 1.0 + gaussian(&mut rng) * step;
+                parameters.for_each(|parameter| *parameter *= factor);
+            }
+            return;
+        }
+
+        for parameter in parameters {
+
+            if !rng.gen_bool(self.chance) {
+                continue;
+            }
+
+            match self.operator {
+                Operator::Uniform => *parameter += rng.gen_range(-1.0..1.0) * step,
+                Operator::Gaussian => *parameter += gaussian(&mut rng) * step,
+                Operator::Reset => *parameter = rng.gen_range(-1.0..1.0),
+                Operator::LayerScale => unreachable!(),
+            }
+        }
+    }
+
+}
+
+
+/// Samples the standard normal distribution, using the Box-Muller transform
+pub fn gaussian(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+}
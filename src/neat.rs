@@ -0,0 +1,387 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use rand::Rng;
+use rand::rngs::ThreadRng;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::consts::*;
+use crate::mutation::Mutation;
+
+
+/// The id of the node always outputting 1, right after the input nodes
+const BIAS_NODE: usize = SIGHT_INPUT_SIZE;
+const FIRST_OUTPUT_NODE: usize = BIAS_NODE + 1;
+const OUTPUT_SIZE: usize = 4;
+const FIRST_HIDDEN_NODE: usize = FIRST_OUTPUT_NODE + OUTPUT_SIZE;
+
+
+/// Hands out innovation numbers and hidden node ids
+/// The same structural mutation gets the same numbers in every genome, so that genomes can be aligned
+struct Innovations {
+
+    /// The innovation number of the connection between two nodes
+    connections: HashMap<(usize, usize), usize>,
+    /// The id of the node created by splitting the connection with the given innovation number
+    splits: HashMap<usize, usize>,
+    next_innovation: usize,
+    next_node: usize,
+
+}
+
+
+impl Innovations {
+
+    fn connection(&mut self, input: usize, output: usize) -> usize {
+        let next_innovation = &mut self.next_innovation;
+        *self.connections.entry((input, output)).or_insert_with(|| {
+            *next_innovation += 1;
+            *next_innovation - 1
+        })
+    }
+
+
+    fn split(&mut self, innovation: usize) -> usize {
+        let next_node = &mut self.next_node;
+        *self.splits.entry(innovation).or_insert_with(|| {
+            *next_node += 1;
+            *next_node - 1
+        })
+    }
+
+}
+
+
+lazy_static! {
+
+    static ref INNOVATIONS: Mutex<Innovations> = Mutex::new(Innovations {
+        connections: HashMap::new(),
+        splits: HashMap::new(),
+        next_innovation: 0,
+        next_node: FIRST_HIDDEN_NODE,
+    });
+
+}
+
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NodeKind {
+
+    Input,
+    /// Always outputs 1
+    Bias,
+    Hidden,
+    Output,
+
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NodeGene {
+
+    pub id: usize,
+    pub kind: NodeKind,
+
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ConnectionGene {
+
+    /// Identifies the structural mutation that created the connection
+    pub innovation: usize,
+    pub input: usize,
+    pub output: usize,
+    pub weight: f64,
+    /// Disabled connections are kept in the genome, but don't carry any signal
+    pub enabled: bool,
+
+}
+
+
+/// A feed-forward network whose topology evolves, as in NEAT
+/// Starts with the inputs fully connected to the outputs, and grows hidden nodes and connections through mutations
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Genome {
+
+    nodes: Vec<NodeGene>,
+    /// Sorted by innovation number
+    connections: Vec<ConnectionGene>,
+    /// The genome's own mutation step, in the self-adaptive mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    step_size: Option<f64>,
+
+}
+
+
+impl Genome {
+
+    pub fn new() -> Self {
+
+        let mut rng = rand::thread_rng();
+        let mut innovations = INNOVATIONS.lock().expect("Failed to lock the innovations");
+
+        let mut nodes = Vec::with_capacity(FIRST_HIDDEN_NODE);
+        nodes.extend((0..SIGHT_INPUT_SIZE).map(|id| NodeGene { id, kind: NodeKind::Input }));
+        nodes.push(NodeGene { id: BIAS_NODE, kind: NodeKind::Bias });
+        nodes.extend((FIRST_OUTPUT_NODE..FIRST_HIDDEN_NODE).map(|id| NodeGene { id, kind: NodeKind::Output }));
+
+        let mut connections = Vec::with_capacity(FIRST_OUTPUT_NODE * OUTPUT_SIZE);
+        for input in 0..FIRST_OUTPUT_NODE {
+            for output in FIRST_OUTPUT_NODE..FIRST_HIDDEN_NODE {
+                connections.push(ConnectionGene {
+                    innovation: innovations.connection(input, output),
+                    input,
+                    output,
+                    weight: rng.gen_range(-1.0..1.0),
+                    enabled: true,
+                });
+            }
+        }
+        connections.sort_by_key(|connection| connection.innovation);
+
+        Genome {
+            nodes,
+            connections,
+            step_size: None,
+        }
+    }
+
+
+    /// Computes the outputs of the network, evaluating the nodes in topological order
+    /// Hidden nodes use a tanh activation, outputs are left linear
+    pub fn activate(&self, input: &[f64]) -> Vec<f64> {
+
+        let mut outgoing: HashMap<usize, Vec<&ConnectionGene>> = HashMap::new();
+        let mut pending_inputs: HashMap<usize, usize> = HashMap::new();
+        for connection in self.connections.iter().filter(|connection| connection.enabled) {
+            outgoing.entry(connection.input).or_default().push(connection);
+            *pending_inputs.entry(connection.output).or_insert(0) += 1;
+        }
+
+        let mut sums: HashMap<usize, f64> = HashMap::with_capacity(self.nodes.len());
+        let mut outputs = vec![0.0; OUTPUT_SIZE];
+
+        let mut ready: VecDeque<&NodeGene> = self.nodes.iter()
+            .filter(|node| !pending_inputs.contains_key(&node.id))
+            .collect();
+        let nodes: HashMap<usize, &NodeGene> = self.nodes.iter().map(|node| (node.id, node)).collect();
+
+        while let Some(node) = ready.pop_front() {
+
+            let sum = sums.get(&node.id).copied().unwrap_or(0.0);
+            let value = match node.kind {
+                NodeKind::Input => input[node.id],
+                NodeKind::Bias => 1.0,
+                NodeKind::Hidden => sum.tanh(),
+                NodeKind::Output => {
+                    outputs[node.id - FIRST_OUTPUT_NODE] = sum;
+                    sum
+                },
+            };
+
+            for connection in outgoing.get(&node.id).into_iter().flatten() {
+
+                *sums.entry(connection.output).or_insert(0.0) += connection.weight * value;
+
+                let pending = pending_inputs.get_mut(&connection.output).unwrap();
+                *pending -= 1;
+                if *pending == 0 {
+                    ready.push_back(nodes[&connection.output]);
+                }
+            }
+        }
+
+        outputs
+    }
+
+
+    /// Resets a few weights and mutates the others, and sometimes adds a connection or a hidden node
+    pub fn mutate(&mut self, mutation: &Mutation) {
+
+        let mut rng = rand::thread_rng();
+
+        for connection in self.connections.iter_mut() {
+            if rng.gen_bool(NEAT_WEIGHT_RESET_CHANCE) {
+                connection.weight = rng.gen_range(-1.0..1.0);
+            }
+        }
+
+        let weights = self.connections.iter_mut().map(|connection| &mut connection.weight);
+        mutation.apply(weights, &mut self.step_size);
+
+        if rng.gen_bool(NEAT_ADD_CONNECTION_CHANCE) {
+            self.add_connection(&mut rng);
+        }
+
+        if rng.gen_bool(NEAT_ADD_NODE_CHANCE) {
+            self.add_node(&mut rng);
+        }
+    }
+
+
+    /// Connects two unconnected nodes, without creating a cycle
+    /// Gives up if no valid pair is found after a few attempts
+    fn add_connection(&mut self, rng: &mut ThreadRng) {
+
+        for _ in 0..NEAT_ADD_CONNECTION_ATTEMPTS {
+
+            let input = &self.nodes[rng.gen_range(0..self.nodes.len())];
+            let output = &self.nodes[rng.gen_range(0..self.nodes.len())];
+
+            let valid = input.kind != NodeKind::Output
+                && matches!(output.kind, NodeKind::Hidden | NodeKind::Output)
+                && input.id != output.id
+                && !self.connections.iter().any(|c| c.input == input.id && c.output == output.id)
+                && !self.reaches(output.id, input.id);
+
+            if !valid {
+                continue;
+            }
+
+            let (input, output) = (input.id, output.id);
+            let innovation = INNOVATIONS.lock().expect("Failed to lock the innovations").connection(input, output);
+
+            self.insert_connection(ConnectionGene {
+                innovation,
+                input,
+                output,
+                weight: rng.gen_range(-1.0..1.0),
+                enabled: true,
+            });
+
+            return;
+        }
+    }
+
+
+    /// Splits an enabled connection in two with a new hidden node
+    /// The incoming connection has a weight of 1 and the outgoing one keeps the old weight
+    fn add_node(&mut self, rng: &mut ThreadRng) {
+
+        let enabled: Vec<usize> = (0..self.connections.len())
+            .filter(|i| self.connections[*i].enabled)
+            .collect();
+
+        if enabled.is_empty() {
+            return;
+        }
+
+        let split = &mut self.connections[enabled[rng.gen_range(0..enabled.len())]];
+
+        let mut innovations = INNOVATIONS.lock().expect("Failed to lock the innovations");
+        let node = innovations.split(split.innovation);
+
+        if self.nodes.iter().any(|existing| existing.id == node) {
+            return;
+        }
+
+        split.enabled = false;
+        let (input, output, weight) = (split.input, split.output, split.weight);
+
+        let incoming = ConnectionGene {
+            innovation: innovations.connection(input, node),
+            input,
+            output: node,
+            weight: 1.0,
+            enabled: true,
+        };
+        let outgoing = ConnectionGene {
+            innovation: innovations.connection(node, output),
+            input: node,
+            output,
+            weight,
+            enabled: true,
+        };
+
+        drop(innovations);
+
+        self.nodes.push(NodeGene { id: node, kind: NodeKind::Hidden });
+        self.insert_connection(incoming);
+        self.insert_connection(outgoing);
+    }
+
+
+    /// Inserts a connection, keeping the connections sorted by innovation number
+    fn insert_connection(&mut self, connection: ConnectionGene) {
+        let index = self.connections.partition_point(|c| c.innovation < connection.innovation);
+        self.connections.insert(index, connection);
+    }
+
+
+    /// Whether there is a path from one node to another
+    fn reaches(&self, from: usize, to: usize) -> bool {
+
+        let mut visited = HashSet::new();
+        let mut stack = vec![from];
+
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            if visited.insert(node) {
+                stack.extend(self.connections.iter().filter(|c| c.input == node).map(|c| c.output));
+            }
+        }
+
+        false
+    }
+
+
+    /// The NEAT compatibility distance between two genomes
+    /// Counts the excess and disjoint genes, and the mean weight difference of the matching ones
+    /// The gene counts aren't normalized, since all the genomes share the large initial connections
+    pub fn compatibility_distance(&self, other: &Genome) -> f64 {
+
+        let (a, b) = (&self.connections, &other.connections);
+        let (mut i, mut j) = (0, 0);
+        let mut disjoint = 0;
+        let mut matching = 0;
+        let mut weight_difference = 0.0;
+
+        while i < a.len() && j < b.len() {
+            match a[i].innovation.cmp(&b[j].innovation) {
+                std::cmp::Ordering::Equal => {
+                    weight_difference += (a[i].weight - b[j].weight).abs();
+                    matching += 1;
+                    i += 1;
+                    j += 1;
+                },
+                std::cmp::Ordering::Less => {
+                    disjoint += 1;
+                    i += 1;
+                },
+                std::cmp::Ordering::Greater => {
+                    disjoint += 1;
+                    j += 1;
+                },
+            }
+        }
+
+        let excess = (a.len() - i) + (b.len() - j);
+        let mean_weight_difference = weight_difference / matching.max(1) as f64;
+
+        NEAT_EXCESS_COEFFICIENT * excess as f64
+            + NEAT_DISJOINT_COEFFICIENT * disjoint as f64
+            + NEAT_WEIGHT_COEFFICIENT * mean_weight_difference
+    }
+
+
+    /// Makes the innovations of a genome loaded from a file known
+    /// New structural mutations then get numbers that don't collide with the genome's
+    pub fn register_innovations(&self) {
+
+        let mut innovations = INNOVATIONS.lock().expect("Failed to lock the innovations");
+
+        for connection in self.connections.iter() {
+            innovations.connections.entry((connection.input, connection.output)).or_insert(connection.innovation);
+            innovations.next_innovation = innovations.next_innovation.max(connection.innovation + 1);
+        }
+
+        for node in self.nodes.iter() {
+            innovations.next_node = innovations.next_node.max(node.id + 1);
+        }
+    }
+
+}
@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use crate::consts::*;
+use crate::map::Location;
+use crate::snake::Direction;
+
+
+/// What a snake did during its life, summarized to compare it with other snakes
+#[derive(Clone)]
+pub struct Behavior {
+
+    /// The number of steps the head spent in each cell of a coarse grid laid over the map
+    visits: [usize; BEHAVIOR_GRID_SIZE * BEHAVIOR_GRID_SIZE],
+    /// The number of moves going straight, turning left and turning right
+    turns: [usize; 3],
+    last_location: Location,
+
+}
+
+
+impl Behavior {
+
+    pub fn new(head: Location) -> Self {
+        Behavior {
+            visits: [0; BEHAVIOR_GRID_SIZE * BEHAVIOR_GRID_SIZE],
+            turns: [0; 3],
+            last_location: head,
+        }
+    }
+
+
+    /// Records a move of the snake, from the location of its head and the direction it turned to
+    pub fn record(&mut self, head: Location, previous: Direction, direction: Direction) {
+
+        let cell_x = head.x * BEHAVIOR_GRID_SIZE / WORLD_WIDTH;
+        let cell_y = head.y * BEHAVIOR_GRID_SIZE / MAP_HEIGHT;
+        self.visits[cell_y * BEHAVIOR_GRID_SIZE + cell_x] += 1;
+        self.last_location = head;
+
+        let turn = if direction == previous {
+            0
+        } else if direction == previous.clockwise().opposite() {
+            1
+        } else if direction == previous.clockwise() {
+            2
+        } else {
+            return;
+        };
+        self.turns[turn] += 1;
+    }
+
+
+    /// The behavior as a vector: the share of time spent in each cell, the final position
+    /// relative to the size of the map, and the share of each kind of turn
+    pub fn descriptor(&self) -> Vec<f64> {
+
+        let steps = self.visits.iter().sum::<usize>().max(1) as f64;
+        let moves = self.turns.iter().sum::<usize>().max(1) as f64;
+
+        let mut descriptor: Vec<f64> = self.visits.iter().map(|visits| *visits as f64 / steps).collect();
+        descriptor.push(self.last_location.x as f64 / WORLD_WIDTH as f64);
+        descriptor.push(self.last_location.y as f64 / MAP_HEIGHT as f64);
+        descriptor.extend(self.turns.iter().map(|turns| *turns as f64 / moves));
+
+        descriptor
+    }
+
+}
+
+
+/// Rewards snakes for behaving differently from the rest of the population and from the past generations
+#[derive(Clone)]
+pub struct NoveltySearch {
+
+    /// How much novelty counts in the selection, from 0 (fitness only) to 1 (novelty only)
+    pub weight: f64,
+    /// Descriptors of the most novel behaviors of the past generations, oldest first
+    archive: VecDeque<Vec<f64>>,
+
+}
+
+
+impl NoveltySearch {
+
+    pub fn new(weight: f64) -> Self {
+        NoveltySearch {
+            weight: weight.clamp(0.0, 1.0),
+            archive: VecDeque::new(),
+        }
+    }
+
+
+    pub fn archive_size(&self) -> usize {
+        self.archive.len()
+    }
+
+
+    /// Scores the novelty of each behavior as the mean distance to its nearest neighbours,
+    /// among the other behaviors and the archive
+    /// The most novel behaviors are then added to the archive
+    pub fn evaluate(&mut self, descriptors: &[Vec<f64>]) -> Vec<f64> {
+
+        let novelty: Vec<f64> = descriptors.iter().enumerate().map(|(i, descriptor)| {
+
+            let mut distances: Vec<f64> = descriptors.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, other)| other)
+                .chain(self.archive.iter())
+                .map(|other| distance(descriptor, other))
+                .collect();
+
+            distances.sort_by(|a, b| a.total_cmp(b));
+            distances.truncate(NOVELTY_NEIGHBOURS);
+
+            distances.iter().sum::<f64>() / distances.len().max(1) as f64
+        }).collect();
+
+        let mut by_novelty: Vec<usize> = (0..descriptors.len()).collect();
+        by_novelty.sort_by(|a, b| novelty[*b].total_cmp(&novelty[*a]));
+
+        for i in by_novelty.into_iter().take(NOVELTY_ARCHIVE_ADDITIONS) {
+            self.archive.push_back(descriptors[i].clone());
+        }
+        while self.archive.len() > MAX_NOVELTY_ARCHIVE_SIZE {
+            self.archive.pop_front();
+        }
+
+        novelty
+    }
+
+}
+
+
+/// The euclidean distance between two descriptors
+fn distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt()
+}
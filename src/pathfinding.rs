@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+
+use crate::consts::*;
+use crate::map::{Block, Location, Map};
+use crate::snake::Direction;
+
+
+/// Whether a snake can enter the block without risking its life
+pub fn is_safe(block: Block) -> bool {
+    match block {
+        Block::Void => true,
+        Block::Item(item) => !item.is_lethal(),
+        _ => false,
+    }
+}
+
+
+/// Whether the block holds an item that makes the snake grow
+pub fn is_target(block: Block) -> bool {
+    matches!(block, Block::Item(item) if item.growth() > 0)
+}
+
+
+/// The first move of the shortest safe path from the head to the closest item that makes the snake grow
+/// Bodies, walls and poison are avoided. Falls back to any safe move if no such item can be reached,
+/// and returns None if there is no safe move at all
+pub fn direction_to_food(map: &Map, head: Location) -> Option<Direction> {
+
+    // The first move of the shortest path found to each location
+    let mut first_moves: Vec<Vec<Option<Direction>>> = vec![vec![None; WORLD_WIDTH]; MAP_HEIGHT];
+    let mut queue = VecDeque::new();
+    let mut fallback = None;
+
+    for direction in Direction::ALL {
+        if let Some(next) = map.trans(head, 1, direction) {
+            if is_safe(map.get(next)) && first_moves[next.y][next.x].is_none() {
+                first_moves[next.y][next.x] = Some(direction);
+                queue.push_back(next);
+                fallback = fallback.or(Some(direction));
+            }
+        }
+    }
+
+    while let Some(location) = queue.pop_front() {
+
+        let first_move = first_moves[location.y][location.x];
+
+        if is_target(map.get(location)) {
+            return first_move;
+        }
+
+        for direction in Direction::ALL {
+            if let Some(next) = map.trans(location, 1, direction) {
+                if next != head && is_safe(map.get(next)) && first_moves[next.y][next.x].is_none() {
+                    first_moves[next.y][next.x] = first_move;
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    fallback
+}
@@ -0,0 +1,80 @@
+use rand::Rng;
+
+use crate::brain::{argmax_direction, softmax};
+use crate::snake::Direction;
+
+
+/// How the outputs of a brain are turned into a direction
+#[derive(Clone, Copy)]
+pub struct Policy {
+
+    /// If set, the direction is sampled from the softmax of the outputs at this temperature,
+    /// otherwise the direction with the highest output is taken
+    /// Higher temperatures make the choice more random
+    pub temperature: Option<f64>,
+    /// Whether the snake is kept from turning back into its own neck
+    pub mask_reverse: bool,
+
+}
+
+
+impl Policy {
+
+    pub fn new() -> Self {
+        Policy {
+            temperature: None,
+            mask_reverse: false,
+        }
+    }
+
+
+    /// The same policy, always taking the direction with the highest output
+    pub fn greedy(&self) -> Self {
+        Policy {
+            temperature: None,
+            ..*self
+        }
+    }
+
+
+    /// The chance of choosing each direction, in the order of `Direction::ALL`
+    /// The reverse of the current direction has no chance of being chosen if it's masked
+    pub fn probabilities(&self, outputs: &[f64], current: Direction) -> Vec<f64> {
+        let temperature = self.temperature.unwrap_or(1.0);
+        let values: Vec<f64> = self.masked(outputs, current).iter().map(|value| value / temperature).collect();
+        softmax(&values)
+    }
+
+
+    pub fn choose(&self, outputs: &[f64], current: Direction) -> Direction {
+
+        if self.temperature.is_none() {
+            return argmax_direction(&self.masked(outputs, current));
+        }
+
+        let probabilities = self.probabilities(outputs, current);
+        let mut remaining = rand::thread_rng().gen::<f64>();
+
+        for (direction, probability) in Direction::ALL.iter().zip(probabilities.iter()) {
+            if remaining < *probability {
+                return *direction;
+            }
+            remaining -= probability;
+        }
+
+        // Rounding errors can leave a tiny remainder, which goes to the most likely direction
+        argmax_direction(&probabilities)
+    }
+
+
+    /// The outputs, with the reverse of the current direction ruled out if it's masked
+    fn masked(&self, outputs: &[f64], current: Direction) -> [f64; 4] {
+        let mut values = [0.0; 4];
+        values.copy_from_slice(outputs);
+        if self.mask_reverse {
+            values[current.opposite().index()] = f64::NEG_INFINITY;
+        }
+        values
+    }
+
+}
@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+
+use rand::Rng;
+
+use crate::brain::{Brain, DenseBrain};
+use crate::consts::*;
+use crate::game_manager::{save_generation_file, GameManager};
+use crate::snake::Direction;
+
+
+/// A single move of a snake, as stored in the replay memory
+struct Transition {
+
+    state: Vec<f64>,
+    action: Direction,
+    reward: f64,
+    /// None if the snake died during the move
+    next_state: Option<Vec<f64>>,
+
+}
+
+
+/// The chance of moving randomly after the given number of moves, decreasing linearly
+fn epsilon(moves: usize) -> f64 {
+    let progress = (moves as f64 / RL_EPSILON_DECAY_MOVES as f64).min(1.0);
+    RL_START_EPSILON + (RL_MIN_EPSILON - RL_START_EPSILON) * progress
+}
+
+
+/// The largest estimated value among the moves
+fn max_value(brain: &DenseBrain, state: &[f64]) -> f64 {
+    brain.forward(state).into_iter().fold(f64::NEG_INFINITY, f64::max)
+}
+
+
+/// Trains a dense brain with deep Q-learning, learning from each move instead of from whole games
+///
+/// Every episode plays a generation headless with all the snakes driven by the brain, moving randomly
+/// with a decreasing chance. The brain learns to estimate the discounted reward of each move from
+/// batches of past moves drawn from a replay memory, against a target copy of itself that is only
+/// updated periodically. Moves are rewarded for the fitness they earn, and penalized for dying and
+/// for the time they take.
+///
+/// Training is long and its progress uneven, so the brain is checkpointed every `RL_CHECKPOINT_INTERVAL`
+/// episodes as well as at the end
+pub fn train(mut game_manager: GameManager, episodes: usize) {
+
+    game_manager.set_quiet(true);
+
+    let mut online: DenseBrain = game_manager.first_dense_brain("Reinforcement learning");
+    let mut target = online.clone();
+
+    let mut replay: VecDeque<Transition> = VecDeque::with_capacity(RL_REPLAY_CAPACITY);
+    let mut rng = rand::thread_rng();
+    let mut moves = 0;
+    let mut updates = 0;
+
+    for episode in 1..=episodes {
+
+        game_manager.spawn_generation(vec![Brain::Dense(online.clone()); GENERATION_SIZE]);
+
+        let mut total_loss = 0.0;
+        let mut losses = 0;
+
+        for _ in 0..RL_MAX_EPISODE_STEPS {
+
+            if game_manager.population_count() == 0 {
+                break;
+            }
+
+            let states = game_manager.observations();
+            let fitness = game_manager.fitness();
+            let exploration = epsilon(moves);
+
+            let actions: Vec<Option<Direction>> = states.iter()
+                .map(|state| state.as_ref().map(|state| {
+                    if rng.gen_bool(exploration) {
+                        Direction::ALL[rng.gen_range(0..Direction::ALL.len())]
+                    } else {
+                        online.choose(state)
+                    }
+                }))
+                .collect();
+
+            game_manager.step_with(&actions);
+
+            let next_states = game_manager.observations();
+            let next_fitness = game_manager.fitness();
+
+            for (i, state) in states.into_iter().enumerate() {
+
+                let (Some(state), Some(action)) = (state, actions[i]) else {
+                    continue;
+                };
+
+                let next_state = next_states[i].clone();
+                let mut reward = (next_fitness[i] - fitness[i]) * RL_FITNESS_REWARD + RL_STEP_REWARD;
+                if next_state.is_none() {
+                    reward += RL_DEATH_REWARD;
+                }
+
+                if replay.len() == RL_REPLAY_CAPACITY {
+                    replay.pop_front();
+                }
+                replay.push_back(Transition { state, action, reward, next_state });
+                moves += 1;
+            }
+
+            if replay.len() < RL_BATCH_SIZE {
+                continue;
+            }
+
+            // Learn from a batch of past moves
+            let batch: Vec<usize> = rand::seq::index::sample(&mut rng, replay.len(), RL_BATCH_SIZE).into_vec();
+            for i in batch {
+                let transition = &replay[i];
+                let value = transition.reward + match &transition.next_state {
+                    Some(next_state) => RL_DISCOUNT * max_value(&target, next_state),
+                    None => 0.0,
+                };
+                total_loss += online.regress(&transition.state, transition.action.index(), value, RL_LEARNING_RATE);
+                losses += 1;
+            }
+
+            updates += 1;
+            if updates % RL_TARGET_UPDATE_INTERVAL == 0 {
+                target = online.clone();
+            }
+        }
+
+        let fitness = game_manager.fitness();
+        println!(
+            "Episode {}: mean fitness {:.2}, best fitness {:.1}, exploration {:.2}, loss {:.4}",
+            episode,
+            fitness.iter().sum::<f64>() / fitness.len().max(1) as f64,
+            fitness.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            epsilon(moves),
+            total_loss / losses.max(1) as f64
+        );
+
+        if episode % RL_CHECKPOINT_INTERVAL == 0 || episode == episodes {
+            let brain = Brain::Dense(online.clone());
+            let brains: Vec<&Brain> = std::iter::repeat_n(&brain, GENERATION_SIZE).collect();
+            save_generation_file(&brains, episode);
+        }
+    }
+}
+
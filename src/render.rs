@@ -1,5 +1,5 @@
 use graphics::{Context, Transformed};
-use graphics::types::Color;
+use graphics::types::{Color, Matrix2d};
 use opengl_graphics::GlGraphics;
 use piston::RenderArgs;
 use piston_window::{Glyphs, PistonWindow};
@@ -10,7 +10,78 @@ use crate::map::Location;
 
 pub trait Drawable {
 
-    fn draw(&self, args: &RenderArgs, gl: &mut GlGraphics, window: &mut PistonWindow, event: &piston::Event);
+    fn draw(&self, args: &RenderArgs, gl: &mut GlGraphics, window: &mut PistonWindow, event: &piston::Event, camera: &Camera);
+
+}
+
+
+/// Describes how the map is projected on the window
+/// Allows zooming in and following a point of the map
+pub struct Camera {
+
+    pub zoom: f64,
+    /// The window coordinates (at zoom 1) shown at the center of the map area
+    focus: WindowCoordinates,
+
+}
+
+
+impl Camera {
+
+    pub fn new() -> Self {
+        Camera {
+            zoom: 1.0,
+            focus: Camera::map_center(),
+        }
+    }
+
+
+    /// The center of the map area on the window
+    fn map_center() -> WindowCoordinates {
+        WindowCoordinates::new(
+            WIN_WIDTH / 2.0,
+            MAP_WIDTH + (WIN_HEIGHT - MAP_WIDTH) / 2.0
+        )
+    }
+
+
+    /// Center the camera on the given map location
+    pub fn follow(&mut self, location: Location) {
+        let position = WindowCoordinates::from_map_location(location);
+        self.focus = WindowCoordinates::new(
+            position.x + BLOCK_SIZE / 2.0,
+            position.y + BLOCK_SIZE / 2.0
+        );
+    }
+
+
+    /// Reset the camera to show the whole map
+    pub fn reset(&mut self) {
+        self.zoom = 1.0;
+        self.focus = Camera::map_center();
+    }
+
+
+    /// Applies the camera projection to the given transform
+    pub fn transform(&self, transform: Matrix2d) -> Matrix2d {
+        let center = Camera::map_center();
+        transform
+            .trans(center.x, center.y)
+            .zoom(self.zoom)
+            .trans(-self.focus.x, -self.focus.y)
+    }
+
+
+    /// Converts a point on the window to the map location it shows, if any
+    pub fn to_map_location(&self, position: &WindowCoordinates) -> Option<Location> {
+        let center = Camera::map_center();
+
+        // Undo the camera projection
+        let x = (position.x - center.x) / self.zoom + self.focus.x;
+        let y = (position.y - center.y) / self.zoom + self.focus.y;
+
+        WindowCoordinates::new(x, y).to_map_location()
+    }
 
 }
 
@@ -38,6 +109,20 @@ impl WindowCoordinates {
         )
     }
 
+
+    /// Inverse of `from_map_location`
+    /// Returns None if the coordinates fall outside the map
+    pub fn to_map_location(&self) -> Option<Location> {
+        let x = (self.x / BLOCK_SIZE).floor();
+        let y = ((self.y - MAP_WIDTH) / BLOCK_SIZE).floor();
+
+        if x < 0.0 || y < 0.0 || x >= WORLD_WIDTH as f64 || y >= MAP_HEIGHT as f64 {
+            return None;
+        }
+
+        Some(Location::new(x as usize, y as usize))
+    }
+
 }
 
 
@@ -53,6 +138,20 @@ pub fn render_block(color: Color, position: WindowCoordinates, context: &Context
 }
 
 
+/// Renders the border of a square area of the map
+pub fn render_outline(color: Color, position: WindowCoordinates, size: f64, context: &Context, gl: &mut GlGraphics) {
+
+    let square = graphics::rectangle::square(
+        position.x,
+        position.y,
+        size
+    );
+
+    graphics::Rectangle::new_border(color, OUTLINE_RADIUS)
+        .draw(square, &context.draw_state, context.transform, gl);
+}
+
+
 pub fn render_text(text: &str, font: &mut Glyphs, coordinates: WindowCoordinates, window: &mut PistonWindow, event: &piston::Event) {
 
     window.draw_2d(event, |context, graphics, device| {
@@ -75,3 +174,51 @@ pub fn clear_screen(gl: &mut GlGraphics) {
     graphics::clear(BACKGROUND_COLOR, gl);
 }
 
+
+/// Converts a color from HSV to RGB
+/// All the components are in the range [0, 1]
+pub fn hue_to_color(hue: f64, saturation: f64, value: f64) -> Color {
+
+    let sector = (hue.rem_euclid(1.0) * 6.0).floor();
+    let fraction = hue.rem_euclid(1.0) * 6.0 - sector;
+
+    let p = value * (1.0 - saturation);
+    let q = value * (1.0 - fraction * saturation);
+    let t = value * (1.0 - (1.0 - fraction) * saturation);
+
+    let (r, g, b) = match sector as usize {
+        0 => (value, t, p),
+        1 => (q, value, p),
+        2 => (p, value, t),
+        3 => (p, q, value),
+        4 => (t, p, value),
+        _ => (value, p, q),
+    };
+
+    [r as f32, g as f32, b as f32, 1.0]
+}
+
+
+/// Returns a lighter version of the given body color, used for the head
+pub fn head_color(color: Color) -> Color {
+    [
+        color[0] + (1.0 - color[0]) * HEAD_LIGHTEN,
+        color[1] + (1.0 - color[1]) * HEAD_LIGHTEN,
+        color[2] + (1.0 - color[2]) * HEAD_LIGHTEN,
+        color[3],
+    ]
+}
+
+
+/// Covers the topbar area, so that the zoomed map doesn't overlap it
+pub fn clear_topbar(args: &RenderArgs, gl: &mut GlGraphics) {
+    gl.draw(args.viewport(), |context: Context, gl: &mut GlGraphics| {
+        graphics::rectangle(
+            BACKGROUND_COLOR,
+            [0.0, 0.0, WIN_WIDTH, TOPBAR_HEIGHT],
+            context.transform,
+            gl
+        );
+    });
+}
+
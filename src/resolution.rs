@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crate::map::{Block, Location, Map};
+use crate::snake::{DeathCause, Snake};
+
+
+/// The result of a tick for a single snake
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+
+    /// The snake is dead and doesn't take part in the tick
+    Idle,
+    /// The snake moves its head to the location, entering the given block
+    Move(Location, Block),
+    /// The snake dies without moving
+    Die(DeathCause),
+
+}
+
+
+/// Resolves the moves all the snakes chose in the same tick, independently of their order
+///
+/// `targets` holds, for each alive snake, the location its head wants to move to,
+/// or None if it would leave the map. Dead snakes have no target.
+///
+/// The rules are applied to all the snakes at once:
+///
+/// 1. A snake dies if it leaves the map, hits a wall, or eats poison that makes it too short
+/// 2. Head-on: all the snakes moving into the same location die
+/// 3. A snake moving into the current location of any head dies, since that location becomes a neck.
+///    In particular, two snakes swapping places both die
+/// 4. A snake moving into a body bit dies, unless the bit is the tail of a snake that moves
+///    this tick without growing, and thus vacates it. This holds for the snake's own tail too
+///
+/// Snakes that die don't move, so their tails stay in place. Rule 4 is repeated until no more
+/// snakes die, so that moving into the tail of a dying snake is always a collision.
+pub fn resolve(snakes: &[Snake], targets: &[Option<Option<Location>>], map: &Map) -> Vec<Outcome> {
+
+    let mut outcomes: Vec<Outcome> = targets.iter()
+        .map(|target| match target {
+            None => Outcome::Idle,
+            Some(None) => Outcome::Die(DeathCause::Wall),
+            Some(Some(location)) => Outcome::Move(*location, map.get(*location)),
+        })
+        .collect();
+
+    // Rule 1: static obstacles and lethal items
+    for (snake, outcome) in snakes.iter().zip(outcomes.iter_mut()) {
+        if let Outcome::Move(_, block) = *outcome {
+            if block == Block::Wall {
+                *outcome = Outcome::Die(DeathCause::Wall);
+            } else if snake.dies_entering(block) {
+                *outcome = Outcome::Die(DeathCause::Poison);
+            }
+        }
+    }
+
+    // Rule 2: head-on collisions
+    let mut entering: HashMap<Location, usize> = HashMap::new();
+    for target in targets.iter().flatten().flatten() {
+        *entering.entry(*target).or_insert(0) += 1;
+    }
+    for outcome in outcomes.iter_mut() {
+        if let Outcome::Move(location, _) = *outcome {
+            if entering[&location] > 1 {
+                *outcome = Outcome::Die(DeathCause::HeadOn);
+            }
+        }
+    }
+
+    // Rules 3 and 4: collisions with bodies, until no more snakes die
+    loop {
+
+        let mut changed = false;
+
+        for i in 0..outcomes.len() {
+
+            let (location, block) = match outcomes[i] {
+                Outcome::Move(location, block) => (location, block),
+                _ => continue,
+            };
+
+            let hit = match block {
+                Block::SnakeHead(owner) => Some(owner),
+                Block::SnakeTail(owner) if !vacates(&snakes[owner], outcomes[owner], location) => Some(owner),
+                _ => None,
+            };
+
+            if let Some(owner) = hit {
+                outcomes[i] = Outcome::Die(DeathCause::Body(owner));
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    outcomes
+}
+
+
+/// Whether the snake frees the given location this tick
+fn vacates(snake: &Snake, outcome: Outcome, location: Location) -> bool {
+    match outcome {
+        Outcome::Move(_, entering) => snake.tail() == location && !snake.grows_entering(entering),
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::brain::BrainKind;
+    use crate::consts::*;
+    use crate::layout::Layout;
+    use crate::map::Item;
+
+
+    fn boxed_map() -> Map {
+        Map::from_layout(&Layout::boxed(), false)
+    }
+
+
+    /// Places a snake with the given body on the map, head first
+    /// Snakes must be placed in the order of their ids
+    fn place(bits: &[(usize, usize)], map: &mut Map, snakes: &mut Vec<Snake>) {
+
+        // Spawned away from the others, then moved to the given body
+        let mut snake = Snake::spawn_new(BrainKind::Dense, Location::new(10 + 10 * snakes.len(), MAP_HEIGHT - 10), map);
+        map.remove_body(&snake.bits);
+
+        snake.bits = bits.iter().map(|(x, y)| Location::new(*x, *y)).collect();
+        map.set_head_block(snake.bits[0], snake.id);
+        for bit in &snake.bits[1..] {
+            map.set_tail_block(*bit, snake.id);
+        }
+
+        snakes.push(snake);
+    }
+
+
+    fn to(x: usize, y: usize) -> Option<Option<Location>> {
+        Some(Some(Location::new(x, y)))
+    }
+
+
+    #[test]
+    fn head_on_kills_both() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(10, 10), (9, 10), (8, 10)], &mut map, &mut snakes);
+        place(&[(12, 10), (13, 10), (14, 10)], &mut map, &mut snakes);
+
+        let outcomes = resolve(&snakes, &[to(11, 10), to(11, 10)], &map);
+
+        assert_eq!(outcomes, vec![Outcome::Die(DeathCause::HeadOn), Outcome::Die(DeathCause::HeadOn)]);
+    }
+
+
+    #[test]
+    fn swapping_heads_kills_both() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(10, 10), (9, 10), (8, 10)], &mut map, &mut snakes);
+        place(&[(11, 10), (12, 10), (13, 10)], &mut map, &mut snakes);
+
+        let outcomes = resolve(&snakes, &[to(11, 10), to(10, 10)], &map);
+
+        assert_eq!(outcomes, vec![Outcome::Die(DeathCause::Body(1)), Outcome::Die(DeathCause::Body(0))]);
+    }
+
+
+    #[test]
+    fn following_into_a_vacated_tail() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(10, 10), (9, 10), (8, 10)], &mut map, &mut snakes);
+        place(&[(7, 10), (6, 10), (5, 10)], &mut map, &mut snakes);
+
+        let outcomes = resolve(&snakes, &[to(11, 10), to(8, 10)], &map);
+
+        assert_eq!(outcomes, vec![
+            Outcome::Move(Location::new(11, 10), Block::Void),
+            Outcome::Move(Location::new(8, 10), Block::SnakeTail(0)),
+        ]);
+    }
+
+
+    #[test]
+    fn entering_the_tail_of_a_growing_snake() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(10, 10), (9, 10), (8, 10)], &mut map, &mut snakes);
+        place(&[(7, 10), (6, 10), (5, 10)], &mut map, &mut snakes);
+        map.set_item_block(Location::new(11, 10), Item::Apple);
+
+        let outcomes = resolve(&snakes, &[to(11, 10), to(8, 10)], &map);
+
+        assert_eq!(outcomes, vec![
+            Outcome::Move(Location::new(11, 10), Block::Item(Item::Apple)),
+            Outcome::Die(DeathCause::Body(0)),
+        ]);
+    }
+
+
+    #[test]
+    fn entering_the_tail_of_a_dying_snake() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(1, 10), (2, 10), (3, 10)], &mut map, &mut snakes);
+        place(&[(4, 11), (5, 11), (6, 11)], &mut map, &mut snakes);
+
+        let outcomes = resolve(&snakes, &[to(0, 10), to(3, 10)], &map);
+
+        assert_eq!(outcomes, vec![Outcome::Die(DeathCause::Wall), Outcome::Die(DeathCause::Body(0))]);
+    }
+
+
+    #[test]
+    fn chain_of_deaths_through_tails() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(1, 10), (2, 10), (3, 10)], &mut map, &mut snakes);
+        place(&[(4, 11), (5, 11), (6, 11)], &mut map, &mut snakes);
+        place(&[(7, 12), (7, 13), (7, 14)], &mut map, &mut snakes);
+
+        // The second snake dies entering the tail of the first, so the third one hits its tail too
+        let outcomes = resolve(&snakes, &[to(0, 10), to(3, 10), to(6, 11)], &map);
+
+        assert_eq!(outcomes, vec![
+            Outcome::Die(DeathCause::Wall),
+            Outcome::Die(DeathCause::Body(0)),
+            Outcome::Die(DeathCause::Body(1)),
+        ]);
+    }
+
+
+    #[test]
+    fn entering_its_own_tail() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(10, 10), (11, 10), (11, 11), (10, 11)], &mut map, &mut snakes);
+
+        let outcomes = resolve(&snakes, &[to(10, 11)], &map);
+        assert_eq!(outcomes, vec![Outcome::Move(Location::new(10, 11), Block::SnakeTail(0))]);
+
+        // Any other bit of its body kills it
+        let outcomes = resolve(&snakes, &[to(11, 10)], &map);
+        assert_eq!(outcomes, vec![Outcome::Die(DeathCause::Body(0))]);
+    }
+
+
+    #[test]
+    fn dead_snakes_are_idle_and_leaving_the_map_kills() {
+
+        let mut map = boxed_map();
+        let mut snakes = Vec::new();
+        place(&[(10, 10), (9, 10), (8, 10)], &mut map, &mut snakes);
+        place(&[(10, 20), (9, 20), (8, 20)], &mut map, &mut snakes);
+
+        let outcomes = resolve(&snakes, &[None, Some(None)], &map);
+
+        assert_eq!(outcomes, vec![Outcome::Idle, Outcome::Die(DeathCause::Wall)]);
+    }
+
+}
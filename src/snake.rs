@@ -1,15 +1,23 @@
 use graphics::Context;
+use graphics::types::Color;
 use opengl_graphics::GlGraphics;
 use piston::RenderArgs;
 use piston_window::PistonWindow;
 
-use crate::brain::Brain;
-use crate::map::{Location, Map, Block, SubmapMatrix};
-use crate::render::{render_block, Drawable, WindowCoordinates};
+use crate::brain::{encode_sight, encode_sight_into, Brain, BrainKind};
+use crate::map::{Location, Map, Block, Item};
+use crate::render::{render_block, hue_to_color, head_color, Camera, Drawable, WindowCoordinates};
+use crate::novelty::Behavior;
+use crate::mutation::Mutation;
+use crate::controller::Controller;
+use crate::policy::Policy;
 use crate::consts::*;
 
+use rand::Rng;
+use serde_derive::{Serialize, Deserialize};
 
-#[derive(Clone, Copy, PartialEq)]
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Direction {
 
     Up,
@@ -22,13 +30,33 @@ pub enum Direction {
 
 impl Direction {
 
-    pub fn random() -> Self {
-        match rand::random::<usize>() % 4 {
-            0 => Direction::Up,
-            1 => Direction::Down,
-            2 => Direction::Left,
-            3 => Direction::Right,
-            _ => panic!("Invalid random number"),
+    pub const ALL: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+
+    /// The change in coordinates after moving by the given amount in this direction
+    pub fn offset(&self, amount: i64) -> (i64, i64) {
+        match self {
+            Direction::Up => (0, -amount),
+            Direction::Down => (0, amount),
+            Direction::Left => (-amount, 0),
+            Direction::Right => (amount, 0),
+        }
+    }
+
+
+    /// The position of the direction in `ALL`, which is also the order of the brains' outputs
+    pub fn index(&self) -> usize {
+        Direction::ALL.iter().position(|direction| direction == self).unwrap()
+    }
+
+
+    /// The direction after a right turn
+    pub fn clockwise(&self) -> Self {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
         }
     }
 
@@ -47,14 +75,59 @@ impl Direction {
 
 pub type SnakeBody = Vec<Location>;
 
+/// Identifies a snake on the map
+/// Corresponds to the order in which snakes were spawned on the map
+pub type SnakeId = usize;
+
+
+/// The reason a snake died
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DeathCause {
+
+    /// Hit a wall or left the map
+    Wall,
+    /// Hit the body of the given snake, possibly its own
+    Body(SnakeId),
+    /// Moved into the same location as another snake
+    HeadOn,
+    /// Ate poison while too short
+    Poison,
+
+}
+
 
 #[derive(Clone)]
 pub struct Snake {
 
+    pub id: SnakeId,
+    /// The hue of the snake's color, in the range [0, 1)
+    pub hue: f64,
     direction: Direction,
     pub bits: SnakeBody,
     pub brain: Brain,
     pub alive: bool,
+    /// The number of steps the snake has been alive for
+    pub age: usize,
+    /// The number of steps since the snake last ate
+    pub hunger: usize,
+    /// The number of bits the snake still has to grow by
+    pending_growth: usize,
+    /// The number of foes that died by hitting this snake's body
+    pub kills: usize,
+    /// The team the snake belongs to. Snakes without a team are alone in the one with their own id
+    pub team: usize,
+    /// The species the snake was assigned to at the end of its generation
+    pub species: usize,
+    pub behavior: Behavior,
+    /// How different the snake's behavior was from the others', computed at the end of its generation
+    pub novelty: f64,
+    /// What chooses the snake's direction, its brain unless it's driven by a script
+    pub controller: Controller,
+    /// Whether the snake is a scripted bot playing alongside the generation
+    pub bot: bool,
+    /// What the snake saw at the last step, encoded as the input of its brain
+    sight_buffer: Vec<f64>,
+    pub death_cause: Option<DeathCause>,
 
 }
 
@@ -66,59 +139,146 @@ impl Snake {
     }
 
 
-    /// Updates the snake and makes it act in the game
-    /// Updates the map accordingly
-    pub fn act(&mut self, map: &mut Map) {
+    /// How well the snake performed, based on the items it ate and the snakes it killed
+    pub fn fitness(&self, kill_reward: f64) -> f64 {
+        self.length() as f64 - INITIAL_SNAKE_LENGTH as f64 + self.kills as f64 * kill_reward
+    }
+
+
+    /// Makes the snake's controller choose the direction to move in
+    /// Returns the location the head will move to, or None if it would leave the map
+    /// The map isn't changed, the move is applied once all the snakes have chosen theirs
+    pub fn plan(&mut self, map: &Map, policy: &Policy) -> Option<Location> {
+        let controller = self.controller;
+        let direction = controller.choose(self, map, policy);
+        self.plan_direction(direction, map)
+    }
 
-        let sight = map.get_submap(self.bits[0]);
-    
-        self.choose_direction(&sight);
 
-        self.advance_and_update_map(map);
+    /// Asks the brain for a direction from what the snake sees, turning its outputs into a direction with the policy
+    pub fn think(&mut self, map: &Map, policy: &Policy) -> Direction {
+        encode_sight_into(&map.get_submap(self.bits[0]), self.id, map.snake_teams(), &mut self.sight_buffer);
+        self.brain.think(&self.sight_buffer, policy, self.direction)
     }
 
 
-    pub fn spawn_with_brain(brain: Brain, location: Location, map: &mut Map) -> Self {
+    /// Makes the snake move in a direction chosen from outside, such as by a trainer
+    /// Returns the location the head will move to, or None if it would leave the map
+    pub fn plan_direction(&mut self, direction: Direction, map: &Map) -> Option<Location> {
+
+        let previous_direction = self.direction;
+        self.set_direction(direction);
+
+        self.behavior.record(self.bits[0], previous_direction, self.direction);
+
+        self.age += 1;
+        self.hunger += 1;
+
+        map.trans(self.bits[0], 1, self.direction)
+    }
+
+
+    pub fn tail(&self) -> Location {
+        *self.bits.last().unwrap()
+    }
+
+
+    /// Whether the snake keeps its last bit when its head enters the given block
+    pub fn grows_entering(&self, block: Block) -> bool {
+        match block {
+            Block::Item(item) => self.pending_growth > 0 || item.growth() > 0,
+            _ => self.pending_growth > 0,
+        }
+    }
 
-        let direction = Direction::random();
+
+    /// Whether entering the given block kills the snake, by making it too short
+    pub fn dies_entering(&self, block: Block) -> bool {
+        match block {
+            Block::Item(item) => item.is_lethal() && self.length() < MIN_SNAKE_LENGTH + item.growth().unsigned_abs() as usize,
+            _ => false,
+        }
+    }
+
+
+    /// Spawns a snake with the given brain and hue, registering it on the map
+    /// The snake faces a random direction among the ones its body fits in
+    /// The brain starts with an empty memory
+    fn spawn(mut brain: Brain, hue: f64, head_location: Location, map: &mut Map) -> Self {
+
+        let directions = map.spawn_directions(head_location);
+        if directions.is_empty() {
+            panic!("No room to spawn a snake at {:?}", head_location);
+        }
+        let direction = directions[rand::thread_rng().gen_range(0..directions.len())];
+        let id = map.add_snake(hue_to_color(hue, TAIL_SATURATION, TAIL_VALUE));
+        brain.reset_state();
 
         Snake {
+            id,
+            hue,
             direction,
-            bits: Snake::spawn_from_head(location, direction, map),
+            bits: Snake::spawn_from_head(head_location, direction, id, map),
             brain,
             alive: true,
+            age: 0,
+            hunger: 0,
+            pending_growth: 0,
+            kills: 0,
+            team: id,
+            species: 0,
+            behavior: Behavior::new(head_location),
+            novelty: 0.0,
+            controller: Controller::Brain,
+            bot: false,
+            sight_buffer: Vec::with_capacity(SIGHT_INPUT_SIZE),
+            death_cause: None,
         }
     }
 
 
+    pub fn spawn_with_brain(brain: Brain, location: Location, map: &mut Map) -> Self {
+        Snake::spawn(brain, rand::random::<f64>(), location, map)
+    }
+
+
     /// Spawns a new snake with the same brain as the parent at the given location
-    pub fn spawn_offspring(&self, location: Location, map: &mut Map) -> Self {
+    /// The offspring's hue is slightly shifted from the parent's, so that lineages are recognizable
+    pub fn spawn_offspring(&self, mutation: &Mutation, location: Location, map: &mut Map) -> Self {
 
-        let direction = Direction::random();
+        let hue_shift = rand::thread_rng().gen_range(-LINEAGE_HUE_SHIFT..=LINEAGE_HUE_SHIFT);
 
-        let mut offspring = Snake {
-            direction,
-            bits: Snake::spawn_from_head(location, direction, map),
-            brain: self.brain.clone(),
-            alive: true,
-        };
+        let mut offspring = Snake::spawn(
+            self.brain.clone(),
+            (self.hue + hue_shift).rem_euclid(1.0),
+            location,
+            map
+        );
 
-        offspring.brain.mutate();
+        offspring.brain.mutate(mutation);
 
         offspring
     }
 
 
-    fn spawn_from_head(head: Location, direction: Direction, map: &mut Map) -> SnakeBody {
+    /// Spawns a copy of the snake at the given location, with the same brain and color
+    pub fn respawn(&self, location: Location, map: &mut Map) -> Self {
+        Snake::spawn(self.brain.clone(), self.hue, location, map)
+    }
+
+
+    fn spawn_from_head(head: Location, direction: Direction, id: SnakeId, map: &mut Map) -> SnakeBody {
 
         let mut bits = Vec::with_capacity(INITIAL_SNAKE_LENGTH);
 
         bits.push(head);
-        map.set_head_block(head);
+        map.set_head_block(head, id);
 
         for i in 1..INITIAL_SNAKE_LENGTH {
-            let bit_location = head.trans(i as i64, direction.opposite());
-            map.set_tail_block(bit_location);
+            let bit_location = map.trans(head, i as i64, direction.opposite()).expect(
+                "The snake's body must fit in the map"
+            );
+            map.set_tail_block(bit_location, id);
             bits.push(bit_location);
         }
 
@@ -126,17 +286,28 @@ impl Snake {
     }
 
 
-    /// Spawns a new snake on the map
-    pub fn spawn_new(head_location: Location, map: &mut Map) -> Self {
+    /// Spawns a new snake with a random brain of the given kind on the map
+    pub fn spawn_new(brain_kind: BrainKind, head_location: Location, map: &mut Map) -> Self {
+        Snake::spawn(Brain::new(brain_kind), rand::random::<f64>(), head_location, map)
+    }
 
-        let direction = Direction::random();
-        
-        Snake {
-            direction,
-            bits: Snake::spawn_from_head(head_location, direction, map),
-            brain: Brain::new(),
-            alive: true,
-        }
+
+    /// Moves the snake to the given team, taking the team's hue
+    pub fn join_team(&mut self, team: usize, hue: f64, map: &mut Map) {
+        self.team = team;
+        self.hue = hue;
+        map.set_snake_team(self.id, team, self.color());
+    }
+
+
+    /// The color of the snake's body
+    pub fn color(&self) -> Color {
+        hue_to_color(self.hue, TAIL_SATURATION, TAIL_VALUE)
+    }
+
+
+    pub fn direction(&self) -> Direction {
+        self.direction
     }
 
 
@@ -153,92 +324,85 @@ impl Snake {
 
 
     /// Kills the snake and frees the blocks it occupied
-    pub fn die(&mut self, map: &mut Map) {
+    /// The body may leave apples behind, depending on the map's corpse rules
+    pub fn die(&mut self, cause: DeathCause, map: &mut Map) {
 
         self.alive = false;
+        self.death_cause = Some(cause);
 
-        // Skip the head, since it wasn't updaten on the map
-        for bit in self.bits.iter() {
-            map.free_block(*bit);
-        }
+        map.remove_body(&self.bits);
     }
 
 
-    /// Move the snake forward and update the map accordingly
-    /// Checks for collisions and deaths
-    pub fn advance_and_update_map(&mut self, map: &mut Map) {
-
-        // Calculate new position of the new head
-        let mut new_head = self.bits[0];
-        match self.direction {
-            Direction::Up => {
-                new_head.y -= 1;
-            },
-            Direction::Down => {
-                new_head.y += 1;
-            },
-            Direction::Left => {
-                new_head.x -= 1;
-            },
-            Direction::Right => {
-                new_head.x += 1;
-            }
+    /// Frees the last bit of the snake, unless it grows by entering the given block
+    /// Called for all the moving snakes before any of them moves, so that heads can enter vacated tails
+    pub fn release_tail(&self, entering: Block, map: &mut Map) {
+        if !self.grows_entering(entering) {
+            map.free_block(self.tail());
         }
+    }
 
-        //println!("Snake moved to location {:?}", head);
-
-        // Check if the snake collided with something and update the map accordingly
-        match map.get(new_head) {
-
-            Block::Wall |
-            Block::SnakeTail |
-            Block::SnakeHead => {
-                // The snake collided with the wall or itself
-                self.die(map);
-            },
-
-            Block::Apple => {
-                // Occupy the new location of the head if the snake didn't die
-                map.set_head_block(new_head);
-
-                // Increase the length of the snake
-                self.add_bit();
-
-                // Don't remove the last bit of the snake from the map since it grew
-            },
-
-            Block::Void => {
-                // Occupy the new location of the head if the snake didn't die
-                map.set_head_block(new_head);
 
-                // Remove the last bit of the snake from the map
-                map.free_block(*self.bits.last().unwrap());
-            }
+    /// Move the snake's head to the given location and update the map accordingly
+    /// The move must have been resolved against the other snakes beforehand, and the tail released
+    pub fn advance_to(&mut self, new_head: Location, entering: Block, map: &mut Map) {
 
+        let shrink = match entering {
+            Block::Item(item) => self.eat(item),
+            _ => 0,
         };
 
-        // If the snake died, don't move it
-        if !self.alive {
-            return;
+        // Occupy the new location of the head
+        map.set_head_block(new_head, self.id);
+
+        if self.pending_growth > 0 {
+            // The last bit of the snake wasn't released since it grew
+            self.add_bit();
+            self.pending_growth -= 1;
         }
 
         // Move the body of the snake first
         for i in (1..self.length()).rev() {
             self.bits[i] = self.bits[i - 1];
             // Update the map accordingly
-            map.set_tail_block(self.bits[i]);
+            map.set_tail_block(self.bits[i], self.id);
         }
 
         // Move the head of the snake
         self.bits[0] = new_head;
 
+        // Remove the bits lost by eating, never going below the minimum length
+        let shrink = shrink.min(self.length().saturating_sub(MIN_SNAKE_LENGTH));
+        for _ in 0..shrink {
+            let bit = self.bits.pop().unwrap();
+            map.free_block(bit);
+        }
+
     }
 
 
-    /// Calculates a new direction for the snake based on the sight input
-    pub fn choose_direction(&mut self, sight_input: &SubmapMatrix) {
-        let direction = self.brain.think(sight_input);
-        self.set_direction(direction);
+    /// Applies the effects of eating the given item
+    /// Returns the number of bits the snake has to lose
+    fn eat(&mut self, item: Item) -> usize {
+
+        if item.is_food() {
+            self.hunger = 0;
+        }
+
+        let growth = item.growth();
+
+        if growth >= 0 {
+            self.pending_growth += growth as usize;
+            return 0;
+        }
+
+        growth.unsigned_abs() as usize
+    }
+
+
+    /// What the snake sees around its head, encoded as the input of a brain
+    pub fn sight(&self, map: &Map) -> Vec<f64> {
+        encode_sight(&map.get_submap(self.bits[0]), self.id, map.snake_teams())
     }
 
 }
@@ -246,14 +410,17 @@ impl Snake {
 
 impl Drawable for Snake {
 
-    fn draw(&self, args: &RenderArgs, gl: &mut GlGraphics, _window: &mut PistonWindow, _event: &piston::Event) {
+    fn draw(&self, args: &RenderArgs, gl: &mut GlGraphics, _window: &mut PistonWindow, _event: &piston::Event, camera: &Camera) {
+
+        gl.draw(args.viewport(), |mut context: Context, gl: &mut GlGraphics| {
 
-        gl.draw(args.viewport(), |context: Context, gl: &mut GlGraphics| {
+            context.transform = camera.transform(context.transform);
 
             // Draw the head first, as it is of a different color
             let head = self.bits[0];
+            let color = self.color();
             render_block(
-                HEAD_COLOR,
+                head_color(color),
                 WindowCoordinates::from_map_location(head),
                 &context,
                 gl
@@ -262,7 +429,7 @@ impl Drawable for Snake {
             // Draw the rest of the snake
             for bit in self.bits.iter().skip(1) {
                 render_block(
-                    TAIL_COLOR,
+                    color,
                     WindowCoordinates::from_map_location(*bit),
                     &context,
                     gl
@@ -275,3 +442,75 @@ impl Drawable for Snake {
 
 }
 
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::layout::Layout;
+    use crate::resolution::{self, Outcome};
+
+
+    /// Moves a lone snake straight ahead, the way the game manager applies moves, until it dies
+    fn run_straight(snake: &mut Snake, map: &mut Map) {
+
+        for _ in 0..WORLD_WIDTH.max(MAP_HEIGHT) {
+
+            let target = snake.plan_direction(snake.direction(), map);
+
+            match resolution::resolve(std::slice::from_ref(snake), &[Some(target)], map)[0] {
+                Outcome::Move(new_head, entering) => {
+                    snake.release_tail(entering, map);
+                    snake.advance_to(new_head, entering, map);
+                },
+                Outcome::Die(cause) => {
+                    snake.die(cause, map);
+                    return;
+                },
+                Outcome::Idle => unreachable!(),
+            }
+        }
+    }
+
+
+    #[test]
+    fn leaving_a_map_without_walls_kills() {
+
+        let layout = Layout {
+            blocks: vec![vec![Block::Void; WORLD_WIDTH]; MAP_HEIGHT],
+            spawn_points: Vec::new(),
+            apple_zones: Vec::new(),
+        };
+        let mut map = Map::from_layout(&layout, false);
+
+        let mut snake = Snake::spawn_new(BrainKind::Dense, Location::new(0, MAP_HEIGHT / 2), &mut map);
+        let start = snake.bits.clone();
+
+        run_straight(&mut snake, &mut map);
+
+        assert!(!snake.alive);
+        assert_eq!(snake.death_cause, Some(DeathCause::Wall));
+
+        // The snake moved along the edge up to the last row in its direction, and left nothing behind
+        assert_ne!(snake.bits, start);
+        assert_eq!(snake.bits[0].x, 0);
+        assert!(snake.bits[0].y == 0 || snake.bits[0].y == MAP_HEIGHT - 1);
+        assert!(snake.bits.iter().all(|bit| map.get(*bit) == Block::Void));
+    }
+
+
+    #[test]
+    fn wrapping_map_keeps_the_snake_alive() {
+
+        let mut map = Map::from_layout(&Layout::boxed(), true);
+
+        let mut snake = Snake::spawn_new(BrainKind::Dense, Location::new(0, 0), &mut map);
+
+        run_straight(&mut snake, &mut map);
+
+        assert!(snake.alive);
+        assert_eq!(map.get(snake.bits[0]), Block::SnakeHead(snake.id));
+    }
+
+}
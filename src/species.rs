@@ -0,0 +1,23 @@
+use crate::brain::Brain;
+
+
+/// Groups similar brains into species
+/// Each brain joins the first species whose representative, its first member, is closer than
+/// the compatibility threshold of its kind of brain
+/// Returns the species of each brain
+pub fn speciate(brains: &[&Brain]) -> Vec<usize> {
+
+    let mut representatives: Vec<&Brain> = Vec::new();
+
+    brains.iter().map(|brain| {
+
+        let species = representatives.iter().position(
+            |representative| brain.distance(representative) < representative.compatibility_threshold()
+        );
+
+        species.unwrap_or_else(|| {
+            representatives.push(brain);
+            representatives.len() - 1
+        })
+    }).collect()
+}
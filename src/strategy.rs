@@ -0,0 +1,85 @@
+use crate::brain::Brain;
+use crate::consts::*;
+use crate::game_manager::{save_generation_file, GameManager};
+use crate::mutation::gaussian;
+
+
+/// Optimizes a dense brain with an evolution strategy
+///
+/// Each iteration samples pairs of opposite perturbations of the mean parameters (antithetic sampling),
+/// plays them headless together with the mean brain, and moves the mean along the estimated
+/// fitness gradient. The fitness is shaped into centered ranks, so that outliers don't dominate the gradient.
+///
+/// The perturbed brains only serve to estimate the gradient and are thrown away after each iteration,
+/// the mean brain is the result, saved once the iterations are done
+pub fn run(mut game_manager: GameManager, iterations: usize) {
+
+    game_manager.set_quiet(true);
+
+    let mut mean = game_manager.first_dense_brain("The evolution strategy");
+
+    let mut parameters = mean.parameters();
+    let pairs = (GENERATION_SIZE - 1) / 2;
+    let mut rng = rand::thread_rng();
+
+    for iteration in 1..=iterations {
+
+        let noise: Vec<Vec<f64>> = (0..pairs)
+            .map(|_| parameters.iter().map(|_| gaussian(&mut rng)).collect())
+            .collect();
+
+        // The mean brain plays first, followed by each pair of opposite perturbations
+        let mut brains = vec![Brain::Dense(mean.clone())];
+        for epsilon in noise.iter() {
+            for sign in [1.0, -1.0] {
+                let perturbed: Vec<f64> = parameters.iter().zip(epsilon.iter())
+                    .map(|(parameter, noise)| parameter + sign * ES_NOISE_STD * noise)
+                    .collect();
+                brains.push(Brain::Dense(mean.with_parameters(&perturbed)));
+            }
+        }
+
+        let fitness = game_manager.evaluate(brains);
+        let ranks = centered_ranks(&fitness[1..]);
+
+        // Estimate the gradient from the difference of the ranks of each pair
+        let scale = ES_LEARNING_RATE / (2 * pairs) as f64 / ES_NOISE_STD;
+        for (pair, epsilon) in noise.iter().enumerate() {
+            let weight = (ranks[2 * pair] - ranks[2 * pair + 1]) * scale;
+            for (parameter, noise) in parameters.iter_mut().zip(epsilon.iter()) {
+                *parameter += weight * noise;
+            }
+        }
+
+        mean = mean.with_parameters(&parameters);
+
+        let best = fitness.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let population_mean = fitness.iter().sum::<f64>() / fitness.len() as f64;
+        println!(
+            "Iteration {}: mean brain fitness {:.1}, population mean fitness {:.2}, best fitness {:.1}",
+            iteration,
+            fitness[0],
+            population_mean,
+            best
+        );
+    }
+
+    let brain = Brain::Dense(mean);
+    let brains: Vec<&Brain> = std::iter::repeat_n(&brain, GENERATION_SIZE).collect();
+    save_generation_file(&brains, iterations);
+}
+
+
+/// Replaces each value with its rank, scaled to the range [-0.5, 0.5]
+fn centered_ranks(values: &[f64]) -> Vec<f64> {
+
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    for (rank, i) in order.into_iter().enumerate() {
+        ranks[i] = rank as f64 / (values.len() - 1).max(1) as f64 - 0.5;
+    }
+
+    ranks
+}
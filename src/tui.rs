@@ -0,0 +1,206 @@
+use std::io::{Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color as TermColor, Colors, Print, SetColors, ResetColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::{execute, queue};
+use graphics::types::Color;
+use piston::input::Key;
+
+use crate::game_manager::{GameManager, GameStatus};
+use crate::map::Location;
+use crate::consts::*;
+
+
+/// Runs the game in the terminal until ESC is pressed
+/// Each terminal cell shows two map rows using the upper half block character
+pub fn run(mut game_manager: GameManager) {
+
+    let mut stdout = std::io::stdout();
+
+    let _guard = TerminalGuard::enter(&mut stdout);
+
+    let frame_delay = Duration::from_secs_f64(1.0 / TUI_FRAMES_PER_SECOND);
+    let mut last_update = Instant::now();
+    let mut last_frame = Instant::now() - frame_delay;
+
+    'game: loop {
+
+        // Input
+        while event::poll(Duration::ZERO).expect("Failed to poll terminal events") {
+
+            if let Event::Key(key_event) = event::read().expect("Failed to read terminal event") {
+
+                if key_event.kind != KeyEventKind::Press {
+                    continue;
+                }
+
+                let ctrl_c = key_event.code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL);
+                if key_event.code == KeyCode::Esc || ctrl_c {
+                    break 'game;
+                }
+
+                if let Some(key) = to_piston_key(key_event.code) {
+                    game_manager.handle_key(key);
+                }
+            }
+        }
+
+        // Updating
+        let now = Instant::now();
+        game_manager.update(&piston::UpdateArgs {
+            dt: (now - last_update).as_secs_f64()
+        });
+        last_update = now;
+
+        // Rendering
+        if now - last_frame >= frame_delay {
+            draw(&game_manager, &mut stdout);
+            last_frame = now;
+        }
+
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+}
+
+
+/// Puts the terminal in raw mode on the alternate screen, and restores it when dropped
+/// The terminal is also restored before a panic message is printed, so that the message is readable
+/// and the terminal stays usable
+struct TerminalGuard;
+
+
+impl TerminalGuard {
+
+    fn enter(stdout: &mut Stdout) -> Self {
+
+        terminal::enable_raw_mode().expect("Failed to enable raw terminal mode");
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All)).expect(
+            "Failed to initialize the terminal"
+        );
+
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            default_hook(info);
+        }));
+
+        TerminalGuard
+    }
+
+}
+
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+
+/// Leaves raw mode and the alternate screen
+/// Errors are ignored, since this also runs while panicking
+fn restore_terminal() {
+    let _ = execute!(std::io::stdout(), ResetColor, Show, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+
+/// Maps a terminal key to the equivalent window key
+fn to_piston_key(code: KeyCode) -> Option<Key> {
+    match code {
+        KeyCode::Char(' ') => Some(Key::Space),
+        KeyCode::Enter => Some(Key::Return),
+        KeyCode::Tab => Some(Key::Tab),
+        KeyCode::Char('r') => Some(Key::R),
+        KeyCode::Char('s') => Some(Key::S),
+        KeyCode::Char('f') => Some(Key::F),
+        KeyCode::Char('z') => Some(Key::Z),
+        _ => None,
+    }
+}
+
+
+fn to_terminal_color(color: Color) -> TermColor {
+    TermColor::Rgb {
+        r: (color[0] * 255.0) as u8,
+        g: (color[1] * 255.0) as u8,
+        b: (color[2] * 255.0) as u8,
+    }
+}
+
+
+/// Draws the topbar and the map on the terminal
+fn draw(game_manager: &GameManager, stdout: &mut Stdout) {
+
+    let map = game_manager.map();
+    let selected = game_manager.selected_snake();
+
+    // The color of a map cell, taking the selection into account
+    let color_at = |x: usize, y: usize| -> Color {
+        if y >= MAP_HEIGHT {
+            return BACKGROUND_COLOR;
+        }
+        let location = Location::new(x, y);
+        match selected {
+            Some(snake) if snake.bits.contains(&location) => SELECTED_COLOR,
+            _ => map.color_at(location),
+        }
+    };
+
+    // Topbar
+
+    let mut status = format!(
+        "Generation: {}  Species: {}  Alive: {}",
+        game_manager.generation_count(),
+        game_manager.species_count(),
+        game_manager.population_count()
+    );
+    if let GameStatus::Paused = game_manager.game_status() {
+        status.push_str("  Paused");
+    }
+
+    queue!(
+        stdout,
+        ResetColor,
+        MoveTo(0, 0),
+        Clear(ClearType::CurrentLine),
+        Print(status),
+        MoveTo(0, 1),
+        Clear(ClearType::CurrentLine),
+        Print(game_manager.selection_stats().unwrap_or_default())
+    ).expect("Failed to draw the topbar");
+
+    // Map, two rows per terminal line
+
+    for (line, y) in (0..MAP_HEIGHT).step_by(2).enumerate() {
+
+        queue!(stdout, MoveTo(0, (line + TUI_TOPBAR_LINES) as u16)).expect("Failed to move the cursor");
+
+        let mut last_colors: Option<(Color, Color)> = None;
+
+        for x in 0..WORLD_WIDTH {
+
+            let colors = (color_at(x, y), color_at(x, y + 1));
+
+            // Only change the colors when needed, to reduce the output size
+            if last_colors != Some(colors) {
+                queue!(stdout, SetColors(Colors::new(
+                    to_terminal_color(colors.0),
+                    to_terminal_color(colors.1)
+                ))).expect("Failed to set the colors");
+                last_colors = Some(colors);
+            }
+
+            queue!(stdout, Print('▀')).expect("Failed to draw the map");
+        }
+
+        queue!(stdout, ResetColor).expect("Failed to reset the colors");
+    }
+
+    stdout.flush().expect("Failed to flush the terminal output");
+
+}
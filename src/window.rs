@@ -0,0 +1,62 @@
+use opengl_graphics::{GlGraphics, OpenGL, TextureSettings};
+use piston::{ButtonEvent, EventLoop, MouseCursorEvent};
+use piston::event_loop::{EventSettings, Events};
+use piston::input::{RenderEvent, UpdateEvent};
+use piston::window::WindowSettings;
+use piston_window::{Glyphs, PistonWindow};
+
+use crate::game_manager::GameManager;
+use crate::{consts::*, font_path};
+
+
+/// Runs the game in an OpenGL window until the window is closed
+pub fn run(mut game_manager: GameManager) {
+
+    // Initialize graphics
+
+    let opengl = OpenGL::V3_2;
+    let mut window: PistonWindow = WindowSettings::new(WIN_TITLE, [WIN_WIDTH, WIN_HEIGHT])
+        .exit_on_esc(true)
+        .build()
+        .expect("Could not create window");
+    let mut gl = GlGraphics::new(opengl);
+
+    let event_settings = EventSettings::new()
+    // Lazy mode is disabled to allow updates to be independent of user input
+        .lazy(false);
+    let mut events = Events::new(event_settings);
+
+    let font = include_bytes!(font_path!());
+
+    let mut glyphs = Glyphs::from_bytes(
+        font,
+        window.create_texture_context(),
+        TextureSettings::new(),
+    ).unwrap();
+
+
+    // Game loop
+
+    while let Some(event) = events.next(&mut window) {
+
+        // Rendering
+        if let Some(args) = event.render_args() {
+            game_manager.draw(&args, &mut gl, &mut window, &event, &mut glyphs);
+        }
+
+        // Updating
+        if let Some(args) = event.update_args() {
+            game_manager.update(&args);
+        }
+
+        // Input
+        if let Some(args) = event.button_args() {
+            game_manager.handle_input(&args);
+        }
+
+        if let Some(position) = event.mouse_cursor_args() {
+            game_manager.handle_cursor(position);
+        }
+    }
+
+}